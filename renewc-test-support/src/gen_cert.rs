@@ -56,7 +56,7 @@ pub fn generate_cert_with_chain<P: PemItem>(
 
     Signed {
         certificate: client_cert,
-        private_key: client_key,
+        private_key: Some(client_key),
         chain,
     }
 }
@@ -22,7 +22,7 @@ impl TestAcme {
 
 impl renewc::ACME for TestAcme {
     async fn renew<P: PemItem, W: Write + Send>(
-        &self,
+        &mut self,
         config: &renewc::Config,
         stdout: &mut W,
         _debug: bool,
@@ -33,6 +33,14 @@ impl renewc::ACME for TestAcme {
         writeln!(stdout, "TestAcme, not signing certificate").expect("printing should not fail");
         Ok(combined)
     }
+
+    async fn renewal_window(
+        &self,
+        _config: &renewc::Config,
+        _info: &renewc::cert::info::Info,
+    ) -> eyre::Result<Option<renewc::cert::info::RenewalWindow>> {
+        Ok(None)
+    }
 }
 
 pub struct TestPrinter;
@@ -89,7 +97,7 @@ mod tests {
     #[tokio::test]
     async fn acme_test_impl_pem_has_private_key() {
         let dir = tempdir().unwrap();
-        let acme = TestAcme::new(valid());
+        let mut acme = TestAcme::new(valid());
         let cert: Signed<Pem> = acme
             .renew(
                 &Config::test(42, &dir.path().join("test_cert")),
@@ -99,7 +107,7 @@ mod tests {
             .await
             .unwrap();
 
-        let private_key = String::from_utf8(cert.private_key.as_bytes()).unwrap();
+        let private_key = String::from_utf8(cert.private_key.unwrap().as_bytes()).unwrap();
         assert!(!private_key.is_empty());
         assert!(private_key.contains("END PRIVATE KEY"));
         assert!(
@@ -69,9 +69,17 @@ impl CheckResult {
 }
 
 pub fn given_existing(config: &Config, cert: Info, stdout: &mut impl Write) -> CheckResult {
-    let new_domains: HashSet<_> = config.domains.iter().collect();
+    // a domain is not missing if a wildcard in the new request covers it
     let prev_domains: HashSet<_> = cert.domains.iter().collect();
-    let missing = prev_domains.difference(&new_domains).map(|s| s.as_str());
+    let missing = prev_domains
+        .iter()
+        .filter(|prev| {
+            !config
+                .domains
+                .iter()
+                .any(|new| cert::info::san_covers(new, prev))
+        })
+        .map(|s| s.as_str());
     let n_missing = missing.clone().count();
     let missing: String = Itertools::intersperse_with(missing, || "\n\t-").collect();
 
@@ -86,6 +94,19 @@ pub fn given_existing(config: &Config, cert: Info, stdout: &mut impl Write) -> C
         }
     }
 
+    // a reused key keeps its type, with a CSR we do not pick the key
+    let new_key = !config.reuse_key && config.csr.is_none();
+    if let Some(prev) = cert
+        .key_type
+        .filter(|prev| new_key && *prev != config.key_type)
+    {
+        warn!(
+            stdout,
+            "The private key type changes from {prev} to {}, make sure everything using the certificate supports that",
+            config.key_type
+        );
+    }
+
     match (config.production, cert.staging, cert.should_renew()) {
         (false, true, _) => {
             CheckResult::accept("Requesting staging cert, certificates will not be valid")
@@ -109,6 +130,15 @@ pub fn given_existing(config: &Config, cert: Info, stdout: &mut impl Write) -> C
                     "Renewing production cert: existing certificate expired {} days, {} hours ago",
                     cert.since_expired().whole_days(),
                     cert.since_expired().whole_hours() % 24)}
+            } else if let Some(window) = &cert.renewal_window {
+                let mut status = format!("Renewing production cert: certificate authority suggests renewing now, existing certificate expires in: {} days, {} hours",
+                  cert.expires_in.whole_days(),
+                  cert.expires_in.whole_hours() % 24);
+                if let Some(url) = &window.explanation_url {
+                    status.push_str(&format!("\n\tsee: {url}"));
+                }
+
+                CheckResult::accept(status)
             } else {
                 let status = format!("Renewing production cert: existing certificate expires soon: {} days, {} hours", 
                   cert.expires_in.whole_days(),
@@ -44,8 +44,8 @@ impl<P: PemItem> std::fmt::Debug for MaybeSigned<P> {
 pub struct Signed<P: PemItem> {
     // PEM encoded
     pub certificate: P,
-    // PEM encoded
-    pub private_key: P,
+    // PEM encoded, None if the certificate was requested using a CSR
+    pub private_key: Option<P>,
     // List of PEM encoded
     pub chain: Vec<P>,
 }
@@ -54,7 +54,13 @@ impl<P: PemItem> std::fmt::Debug for Signed<P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Signed")
             .field("certificate", &"hidden to prevent security leaks")
-            .field("private_key", &"hidden to prevent security leaks")
+            .field(
+                "private_key",
+                &self
+                    .private_key
+                    .as_ref()
+                    .map(|_| "hidden to prevent security leaks"),
+            )
             .field(
                 "chain",
                 &self
@@ -78,9 +84,7 @@ impl<P: PemItem> TryFrom<MaybeSigned<P>> for Signed<P> {
 
         Ok(Self {
             certificate: signed.certificate,
-            private_key: signed
-                .private_key
-                .ok_or_else(|| eyre::eyre!("missing private key"))?,
+            private_key: signed.private_key,
             chain: signed.chain,
         })
     }
@@ -88,10 +92,19 @@ impl<P: PemItem> TryFrom<MaybeSigned<P>> for Signed<P> {
 
 impl<P: PemItem> Signed<P> {
     /// first certificate in full chain must be the domains certificate
-    pub fn from_key_and_fullchain(
-        private_key: String,
-        mut full_chain: String,
-    ) -> eyre::Result<Self> {
+    pub fn from_key_and_fullchain(private_key: String, full_chain: String) -> eyre::Result<Self> {
+        let private_key = PemItem::from_pem(private_key, Label::PrivateKey)
+            .wrap_err("failed to extract private key")?;
+
+        Ok(Self {
+            private_key: Some(private_key),
+            ..Self::from_fullchain(full_chain)?
+        })
+    }
+
+    /// for certificates requested using a CSR, we never see the key.
+    /// First certificate in full chain must be the domains certificate
+    pub fn from_fullchain(mut full_chain: String) -> eyre::Result<Self> {
         let mut certs = Vec::new();
         while let Some(begin_cert) = full_chain.rfind("-----BEGIN CERTIFICATE-----") {
             certs.push(
@@ -112,12 +125,9 @@ impl<P: PemItem> Signed<P> {
             bail!("No chain certificates in full chain")
         }
 
-        let private_key = PemItem::from_pem(private_key, Label::PrivateKey)
-            .wrap_err("failed to extract private key")?;
-
         Ok(Self {
             certificate: signed,
-            private_key,
+            private_key: None,
             chain,
         })
     }
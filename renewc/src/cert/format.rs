@@ -91,7 +91,7 @@ impl Der {
     }
 
     #[must_use]
-    pub(super) fn into_bytes(self) -> Vec<u8> {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
         self.0
     }
 }
@@ -1,24 +1,47 @@
 use std::io::Write;
+use std::net::IpAddr;
 
 use super::format::PemItem;
 use super::{load, Signed};
-use crate::config;
+use crate::config::{self, KeyType};
 
 use color_eyre::eyre;
+use instant_acme::CertificateIdentifier;
 use rand::{self, Rng, SeedableRng};
-use time::Duration;
+use rustls::pki_types::Der;
+use time::{Duration, OffsetDateTime};
 use tracing::instrument;
-use x509_parser::prelude::{GeneralName, Pem};
+use x509_parser::prelude::{GeneralName, ParsedExtension, Pem, X509Certificate};
+use x509_parser::public_key::PublicKey;
+
+/// When the certificate authority would like us to renew, from ACME
+/// Renewal Information (RFC 9773)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenewalWindow {
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    /// page explaining why the window changed, for example a mass revocation
+    pub explanation_url: Option<String>,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Info {
     pub staging: bool,
     pub expires_in: Duration,
+    /// from the start of the validity period till expiry
+    pub lifetime: Duration,
     pub domains: Vec<String>,
+    /// None if the key is not one we can generate
+    pub key_type: Option<KeyType>,
     // unix timestamp of expiration time
     // used to seed rng such that each randomness
     // only changes with a renewed certificate
     pub(crate) seed: u64,
+    /// identifies the certificate for ACME Renewal Information, None if it
+    /// has no authority key identifier
+    pub(crate) ari_cert_id: Option<CertificateIdentifier<'static>>,
+    /// suggested by the certificate authority, if it supports that
+    pub renewal_window: Option<RenewalWindow>,
 }
 
 impl Info {
@@ -34,12 +57,17 @@ impl Info {
         Ok(Some(info))
     }
 
-    /// how far before the expiration date to renew a certificate
+    /// how far before the expiration date to renew a certificate. That is
+    /// in the last third of its lifetime so short lived certificates are
+    /// renewed in time too. Spread out a bit so not everyone renews at once.
     #[instrument(ret, skip(self))]
     pub fn renew_period(&self) -> Duration {
+        let third = self.lifetime.whole_seconds() / 3;
+        let range = third * 9 / 10..third;
+        if range.is_empty() {
+            return Duration::seconds(third);
+        }
         let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
-        let range = Duration::days(8)..Duration::days(10);
-        let range = range.start.whole_seconds()..range.end.whole_seconds();
         let renew_period = rng.gen_range(range);
         Duration::seconds(renew_period)
     }
@@ -48,9 +76,24 @@ impl Info {
         self.expires_in.abs()
     }
 
+    /// a random moment in the renewal window suggested by the certificate
+    /// authority. Like `renew_period` it only changes with a renewed
+    /// certificate.
+    fn renew_at(window: &RenewalWindow, seed: u64) -> OffsetDateTime {
+        let length = (window.end - window.start).whole_seconds();
+        if length <= 0 {
+            return window.start;
+        }
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        window.start + Duration::seconds(rng.gen_range(0..length))
+    }
+
     #[instrument(ret, skip(self))]
     pub(crate) fn should_renew(&self) -> bool {
-        self.expires_in < self.renew_period()
+        match &self.renewal_window {
+            Some(window) => OffsetDateTime::now_utc() >= Self::renew_at(window, self.seed),
+            None => self.expires_in < self.renew_period(),
+        }
     }
 
     #[instrument(ret, skip(self))]
@@ -76,6 +119,10 @@ pub fn analyze(signed: Signed<impl PemItem>) -> eyre::Result<Info> {
         .validity()
         .time_to_expiration()
         .unwrap_or(Duration::ZERO);
+    // none if the validity ends before it starts, that is broken anyway so
+    // assume the common 90 days
+    let lifetime =
+        (cert.validity().not_after - cert.validity().not_before).unwrap_or(Duration::days(90));
     let expires_at = cert
         .validity()
         .not_after
@@ -88,7 +135,7 @@ pub fn analyze(signed: Signed<impl PemItem>) -> eyre::Result<Info> {
             s.value
                 .general_names
                 .iter()
-                .filter_map(unwrap_dns)
+                .filter_map(unwrap_name)
                 .collect()
         })
         .unwrap_or_default();
@@ -96,14 +143,210 @@ pub fn analyze(signed: Signed<impl PemItem>) -> eyre::Result<Info> {
     Ok(Info {
         staging,
         expires_in,
+        lifetime,
         seed: expires_at,
         domains,
+        key_type: key_type(&cert),
+        ari_cert_id: ari_cert_id(&cert),
+        renewal_window: None,
     })
 }
 
-fn unwrap_dns(name: &GeneralName) -> Option<String> {
+/// base64url(authority key identifier) "." base64url(serial number),
+/// see RFC 9773 section 4.1
+fn ari_cert_id(cert: &X509Certificate) -> Option<CertificateIdentifier<'static>> {
+    let key_id = cert
+        .extensions()
+        .iter()
+        .find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityKeyIdentifier(aki) => aki.key_identifier.as_ref(),
+            _ => None,
+        })?;
+    Some(CertificateIdentifier::new(
+        Der::from_slice(key_id.0),
+        Der::from_slice(cert.raw_serial()),
+    ))
+}
+
+fn key_type(cert: &X509Certificate) -> Option<KeyType> {
+    const ED25519: &str = "1.3.101.112";
+    const P256: &str = "1.2.840.10045.3.1.7";
+    const P384: &str = "1.3.132.0.34";
+
+    let spki = cert.public_key();
+    if spki.algorithm.algorithm.to_id_string() == ED25519 {
+        return Some(KeyType::Ed25519);
+    }
+    match spki.parsed().ok()? {
+        PublicKey::RSA(key) => match key.key_size() {
+            2048 => Some(KeyType::Rsa2048),
+            3072 => Some(KeyType::Rsa3072),
+            4096 => Some(KeyType::Rsa4096),
+            _ => None,
+        },
+        PublicKey::EC(_) => {
+            let curve = spki.algorithm.parameters.as_ref()?.as_oid().ok()?;
+            match curve.to_id_string().as_str() {
+                P256 => Some(KeyType::EcdsaP256),
+                P384 => Some(KeyType::EcdsaP384),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// domain or ip address (RFC 8738) in a subject alternative name
+pub(crate) fn unwrap_name(name: &GeneralName) -> Option<String> {
     match name {
-        GeneralName::DNSName(s) => Some(s.to_string()),
+        GeneralName::DNSName(s) => Some(s.to_lowercase()),
+        GeneralName::IPAddress(octets) => {
+            let ip = match <[u8; 4]>::try_from(*octets) {
+                Ok(v4) => IpAddr::from(v4),
+                Err(_) => IpAddr::from(<[u8; 16]>::try_from(*octets).ok()?),
+            };
+            Some(ip.to_string())
+        }
         _other => None,
     }
 }
+
+/// whether a certificate with subject alternative name `san` is valid for
+/// `domain`. A wildcard only matches a single label, `*.example.org` is
+/// valid for `www.example.org` but not `example.org` or
+/// `a.www.example.org`. If `domain` is a wildcard itself it is only
+/// covered by the same wildcard.
+pub(crate) fn san_covers(san: &str, domain: &str) -> bool {
+    if san.eq_ignore_ascii_case(domain) {
+        return true;
+    }
+
+    let Some(base) = san.strip_prefix("*.") else {
+        return false;
+    };
+    match domain.split_once('.') {
+        Some((label, rest)) => label != "*" && rest.eq_ignore_ascii_case(base),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renew::generate_key;
+    use rcgen::{Certificate, CertificateParams};
+    use x509_parser::prelude::FromDer;
+
+    #[test]
+    fn detects_key_type() {
+        for key_type in [
+            KeyType::EcdsaP256,
+            KeyType::EcdsaP384,
+            KeyType::Ed25519,
+            KeyType::Rsa2048,
+        ] {
+            let key_pair = generate_key(key_type).unwrap();
+            let mut params = CertificateParams::new(vec!["example.org".to_owned()]);
+            params.alg = key_pair.algorithm();
+            params.key_pair = Some(key_pair);
+            let der = Certificate::from_params(params)
+                .unwrap()
+                .serialize_der()
+                .unwrap();
+            let (_, cert) = X509Certificate::from_der(&der).unwrap();
+            assert_eq!(super::key_type(&cert), Some(key_type));
+        }
+    }
+
+    #[test]
+    fn ari_cert_id_rfc_example() {
+        // RFC 9773 section 4.1
+        let key_id = [
+            0x69, 0x88, 0x5B, 0x6B, 0x87, 0x46, 0x40, 0x41, 0xE1, 0xB3, 0x7B, 0x84, 0x7B, 0xA0,
+            0xAE, 0x2C, 0xDE, 0x01, 0xC8, 0xD4,
+        ];
+        let serial = [0x00, 0x87, 0x65, 0x43, 0x21];
+        let id = CertificateIdentifier::new(Der::from_slice(&key_id), Der::from_slice(&serial));
+        assert_eq!(id.to_string(), "aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE");
+    }
+
+    #[test]
+    fn renewal_window_decides() {
+        let now = OffsetDateTime::now_utc();
+        let mut info = Info {
+            staging: false,
+            expires_in: Duration::days(60),
+            lifetime: Duration::days(90),
+            domains: Vec::new(),
+            key_type: None,
+            seed: 42,
+            ari_cert_id: None,
+            renewal_window: None,
+        };
+        assert!(!info.should_renew(), "fallback: not yet in last third");
+
+        info.renewal_window = Some(RenewalWindow {
+            start: now - Duration::days(2),
+            end: now - Duration::days(1),
+            explanation_url: None,
+        });
+        assert!(info.should_renew(), "window passed, ca wants renewal");
+
+        info.expires_in = Duration::days(5);
+        info.renewal_window = Some(RenewalWindow {
+            start: now + Duration::days(1),
+            end: now + Duration::days(2),
+            explanation_url: None,
+        });
+        assert!(!info.should_renew(), "window trumps expiry based fallback");
+    }
+
+    #[test]
+    fn renew_in_last_third() {
+        let mut info = Info {
+            staging: false,
+            expires_in: Duration::days(3),
+            lifetime: Duration::days(6),
+            domains: Vec::new(),
+            key_type: None,
+            seed: 42,
+            ari_cert_id: None,
+            renewal_window: None,
+        };
+        for seed in 0..100 {
+            info.seed = seed;
+            let period = info.renew_period();
+            assert!(period > Duration::hours(43), "{period}");
+            assert!(period <= Duration::days(2), "{period}");
+        }
+        assert!(!info.should_renew(), "half of a short lived cert left");
+        info.expires_in = Duration::hours(36);
+        assert!(info.should_renew(), "short lived cert in last third");
+
+        info.lifetime = Duration::days(90);
+        info.expires_in = Duration::days(20);
+        assert!(info.should_renew());
+    }
+
+    #[test]
+    fn wildcard_san() {
+        assert!(san_covers("example.org", "Example.org"));
+        assert!(san_covers("*.example.org", "www.example.org"));
+        assert!(san_covers("*.example.org", "*.example.org"));
+        assert!(!san_covers("*.example.org", "example.org"));
+        assert!(!san_covers("*.example.org", "a.www.example.org"));
+        assert!(!san_covers("www.example.org", "*.example.org"));
+        assert!(!san_covers("*.example.org", "*.www.example.org"));
+    }
+
+    #[test]
+    fn ip_san() {
+        let v4 = GeneralName::IPAddress(&[192, 0, 2, 1]);
+        assert_eq!(unwrap_name(&v4).as_deref(), Some("192.0.2.1"));
+        let mut octets = [0u8; 16];
+        octets[..4].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        octets[15] = 1;
+        let v6 = GeneralName::IPAddress(&octets);
+        assert_eq!(unwrap_name(&v6).as_deref(), Some("2001:db8::1"));
+    }
+}
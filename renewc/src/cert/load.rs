@@ -12,27 +12,32 @@ use super::{MaybeSigned, Signed};
 use super::io::read_any_file;
 
 // TODO: remove Option, report errors upstream as warnings <03-05-23, dvdsk>
-#[instrument(level = "debug", skip(config, stdout), ret)]
 pub fn from_disk<P: PemItem>(
     config: &Config,
     stdout: &mut impl Write,
+) -> eyre::Result<Option<Signed<P>>> {
+    from_output_config(&config.output_config, stdout)
+}
+
+/// Like [`from_disk`] for when there is only an [`OutputConfig`]
+#[instrument(level = "debug", skip(config, stdout), ret)]
+pub fn from_output_config<P: PemItem>(
+    config: &OutputConfig,
+    stdout: &mut impl Write,
 ) -> eyre::Result<Option<Signed<P>>> {
     let Some(MaybeSigned {
         certificate,
         private_key,
         mut chain,
-    }) = load_certificate(&config.output_config)
-        .wrap_err("Failed to load certificates from disk")?
+    }) = load_certificate(config).wrap_err("Failed to load certificates from disk")?
     else {
         return Ok(None);
     };
 
+    // there is no key if the certificate was requested using a CSR
     let private_key = match private_key {
-        Some(key) => key,
-        None => match load_seperate_private_key(config)? {
-            Some(key) => key,
-            None => return Ok(None),
-        },
+        Some(key) => Some(key),
+        None => load_seperate_private_key(config)?,
     };
     if chain.is_empty() {
         chain = load_seperate_chain(config)?;
@@ -52,10 +57,10 @@ pub fn from_disk<P: PemItem>(
 }
 
 #[instrument(level = "debug", skip(config), err)]
-fn load_seperate_chain<P: PemItem>(config: &Config) -> eyre::Result<Vec<P>> {
+fn load_seperate_chain<P: PemItem>(config: &OutputConfig) -> eyre::Result<Vec<P>> {
     let OutputConfig {
         output, chain_path, ..
-    } = &config.output_config;
+    } = config;
 
     let encoding = Encoding::from(output);
     match encoding {
@@ -92,10 +97,10 @@ fn load_seperate_chain<P: PemItem>(config: &Config) -> eyre::Result<Vec<P>> {
 }
 
 #[instrument(level = "debug", skip(config), err)]
-fn load_seperate_private_key<P: PemItem>(config: &Config) -> eyre::Result<Option<P>> {
+fn load_seperate_private_key<P: PemItem>(config: &OutputConfig) -> eyre::Result<Option<P>> {
     let OutputConfig {
         output, key_path, ..
-    } = &config.output_config;
+    } = config;
     let encoding = Encoding::from(output);
 
     let Some(bytes) = read_any_file(key_path.as_path())? else {
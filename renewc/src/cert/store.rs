@@ -1,6 +1,6 @@
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use super::format::PemItem;
 use super::Signed;
@@ -9,7 +9,7 @@ use color_eyre::Help;
 use itertools::Itertools;
 use tracing::{instrument, warn};
 
-use crate::config::{Encoding, Output, OutputConfig};
+use crate::config::{Encoding, Output, OutputConfig, RevokedFiles};
 use crate::{Config, IndentedOut};
 
 #[instrument(level = "debug", skip(certificate))]
@@ -37,12 +37,21 @@ fn write_signed(
     }
 }
 
+/// There is no key if the certificate was requested using a CSR. A key
+/// file left by an earlier certificate is then removed, otherwise it would
+/// be loaded as the key of the new certificate.
 #[instrument(level = "debug", skip(private_key))]
 fn write_key(
     encoding: Encoding,
-    private_key: impl PemItem,
+    private_key: Option<impl PemItem>,
     operation: Operation,
 ) -> eyre::Result<()> {
+    let Some(private_key) = private_key else {
+        return match operation {
+            Operation::Append(_) => Ok(()),
+            Operation::Create(path) => remove_stale_key(path),
+        };
+    };
     let bytes = match encoding {
         Encoding::PEM => private_key.as_bytes(),
         Encoding::DER => private_key.der().into_bytes(),
@@ -66,6 +75,20 @@ fn write_key(
     }
 }
 
+fn remove_stale_key(path: &Path) -> eyre::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => {
+            warn!("removed the private key of the previous certificate: {path:?}");
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+            .wrap_err("Could not remove the private key of the previous certificate")
+            .with_note(|| format!("path: {path:?}"))
+            .note("The new certificate was requested using a CSR, the key does not belong to it"),
+    }
+}
+
 fn write_chain<P: PemItem>(
     encoding: Encoding,
     chain: Vec<P>,
@@ -140,6 +163,7 @@ pub fn on_disk<P: PemItem>(
 
     crate::info!(stdout, "succeeded, storing on disk");
     let chain_len = chain.len();
+    let has_key = private_key.is_some();
     match config.output_config.output {
         Output::PemSingleFile => {
             write_signed(encoding, certificate, Create(cert_path.as_path()))?;
@@ -168,12 +192,71 @@ pub fn on_disk<P: PemItem>(
     }
 
     let mut stdout = IndentedOut::new(stdout);
-    print_status(&mut stdout, &config.output_config, chain_len);
+    print_status(&mut stdout, &config.output_config, chain_len, has_key);
+
+    Ok(())
+}
+
+/// all files of the stored certificate that exist
+fn existing_files(config: &OutputConfig) -> Vec<PathBuf> {
+    let OutputConfig {
+        cert_path,
+        key_path,
+        chain_path,
+        ..
+    } = config;
+    let der_chain = (0..)
+        .map(|i| {
+            chain_path
+                .as_path()
+                .with_file_name(format!("{i}_chain.der"))
+        })
+        .take_while(|path| path.exists());
+    [
+        cert_path.as_path(),
+        key_path.as_path(),
+        chain_path.as_path(),
+    ]
+    .into_iter()
+    .map(Path::to_path_buf)
+    .filter(|path| path.exists())
+    .unique()
+    .chain(der_chain)
+    .collect()
+}
+
+/// Rename or remove the files of a revoked certificate
+#[instrument(level = "debug", skip(config, stdout))]
+pub fn revoked(
+    config: &OutputConfig,
+    files: RevokedFiles,
+    stdout: &mut (impl Write + Send),
+) -> eyre::Result<()> {
+    if files == RevokedFiles::Keep {
+        return Ok(());
+    }
 
+    for path in existing_files(config) {
+        if files == RevokedFiles::Delete {
+            fs::remove_file(&path)
+                .wrap_err("Could not remove revoked certificate file")
+                .with_note(|| format!("path: {path:?}"))?;
+            crate::info!(stdout, "removed: {}", path.display());
+        } else {
+            let mut renamed = path.clone().into_os_string();
+            renamed.push(".revoked");
+            fs::rename(&path, &renamed)
+                .wrap_err("Could not rename revoked certificate file")
+                .with_note(|| format!("path: {path:?}"))?;
+            crate::info!(stdout, "renamed: {} to {renamed:?}", path.display());
+        }
+    }
     Ok(())
 }
 
-fn print_status(stdout: &mut impl Write, config: &OutputConfig, chain_len: usize) {
+/// Without a key, as happens when the certificate was requested using a CSR,
+/// the key is left out of the listing.
+fn print_status(stdout: &mut impl Write, config: &OutputConfig, chain_len: usize, has_key: bool) {
     use std::fmt::Write;
     let OutputConfig {
         output,
@@ -186,7 +269,25 @@ fn print_status(stdout: &mut impl Write, config: &OutputConfig, chain_len: usize
         writeln!(output, "\t- {i}_chain.der").expect("can not fail");
         output
     });
-    let n_der_files = 2 + chain_len;
+    let n_der_files = 1 + usize::from(has_key) + chain_len;
+    let pem_files = |without_key: usize| match without_key + usize::from(has_key) {
+        1 => "a single pem file",
+        2 => "two pem files",
+        _ => "three pem files",
+    };
+    let (key_in_file, key_file, no_key) = if has_key {
+        (
+            "\n        - private key",
+            format!("\n    - {key_path}\n    containing the signed certificates private key"),
+            "",
+        )
+    } else {
+        (
+            "",
+            String::new(),
+            "\n    no private key was written as the certificate was requested using a CSR",
+        )
+    };
 
     match output {
         Output::PemSingleFile => writeln!(
@@ -195,49 +296,43 @@ fn print_status(stdout: &mut impl Write, config: &OutputConfig, chain_len: usize
     - {cert_path} 
     containing in order from top to bottom:
         - signed certificate
-        - certificate chain
-        - private key"
+        - certificate chain{key_in_file}{no_key}"
         ),
         Output::PemSeperateKey => writeln!(
             stdout,
-            "created two pem files:
+            "created {}:
     - {cert_path}, 
     containing in order from top to bottom:
         - signed certificate
-        - certificate chain
-    - {key_path}
-    containing the signed certificates private key"
+        - certificate chain{key_file}{no_key}",
+            pem_files(1)
         ),
         Output::PemSeperateChain => writeln!(
             stdout,
             "created two pem files:
     - {cert_path}, 
     containing in order from top to bottom:
-        - signed certificate
-        - its private key
+        - signed certificate{key_in_file}
     - {chain_path}
-    containing the certificate chain"
+    containing the certificate chain{no_key}"
         ),
         Output::PemAllSeperate => writeln!(
             stdout,
-            "created three pem files:
+            "created {}:
     - {cert_path}, 
     containing the signed certificate
     - {chain_path}
-    containing the certificate chain
-    - {key_path}
-    containing the signed certificates private key"
+    containing the certificate chain{key_file}{no_key}",
+            pem_files(2)
         ),
         Output::Der => writeln!(
             stdout,
             "created {n_der_files} der files:
     - {cert_path}, 
-    containing the signed certificate
-    - {key_path}
-    containing the signed certificates private key
+    containing the signed certificate{key_file}
 
     - chain files each containing part of the certificate chain:
-    {der_chain_files}"
+    {der_chain_files}{no_key}"
         ),
         Output::PKCS12 => todo!(),
         Output::PKCS12SeperateKey => todo!(),
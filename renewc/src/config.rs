@@ -1,19 +1,33 @@
 use std::fmt::Display;
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use color_eyre::eyre;
+use color_eyre::{eyre, Help};
+use instant_acme::CertificateIdentifier;
 use strum::EnumIter;
 
 use crate::diagnostics;
 
-use self::args::RenewArgs;
+use self::args::{AccountArgs, RenewArgs};
 use self::paths::{ChainPath, KeyPath};
 
 mod args;
+mod ca;
+mod csr;
+mod dns;
+mod domains;
+mod key;
 mod paths;
-pub use args::{Commands, InstallArgs, OutputArgs};
+mod revoke;
+pub use args::{AccountAction, CaArgs, Commands, DnsArgs, InstallArgs, OutputArgs, RevokeArgs};
+pub use ca::{CaConfig, Eab};
+pub(crate) use dns::check_dns_name;
+pub use dns::{DnsConfig, DnsProviderKind, ExecConfig, Rfc2136Config, TsigAlgorithm};
+pub use key::KeyType;
 pub use paths::name;
 use paths::CertPath;
+pub use revoke::{RevocationReason, RevokedFiles};
 
 #[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
 /// How to store the output.
@@ -84,6 +98,42 @@ impl Display for Output {
     }
 }
 
+/// What the http challenge server does with requests that are not for a
+/// challenge
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OtherRequests {
+    /// respond with 404
+    #[default]
+    NotFound,
+    /// forward them to this upstream server
+    Proxy(http::Uri),
+    RedirectHttps,
+}
+
+impl OtherRequests {
+    fn new(upstream: Option<http::Uri>, redirect_https: bool) -> eyre::Result<Self> {
+        let Some(upstream) = upstream else {
+            return Ok(if redirect_https {
+                Self::RedirectHttps
+            } else {
+                Self::NotFound
+            });
+        };
+
+        if !matches!(upstream.scheme_str(), Some("http" | "https")) {
+            return Err(eyre::eyre!("Upstream must start with http:// or https://"))
+                .with_note(|| format!("upstream: {upstream}"));
+        }
+        if upstream.path() != "/" || upstream.query().is_some() {
+            return Err(eyre::eyre!(
+                "Upstream can not have a path, requests are forwarded using their own"
+            ))
+            .with_note(|| format!("upstream: {upstream}"));
+        }
+        Ok(Self::Proxy(upstream))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
 pub enum Encoding {
     PEM,
@@ -127,6 +177,10 @@ pub struct OutputConfig {
 
 impl OutputConfig {
     fn new(args: OutputArgs, name: &str) -> Result<Self, eyre::Report> {
+        if Encoding::from(&args.output) == Encoding::PKCS12 {
+            return Err(eyre::eyre!("PKCS12 output is not supported yet"))
+                .with_note(|| format!("output: {}", args.output));
+        }
         Ok(OutputConfig {
             cert_path: CertPath::new(&args.output, &args.certificate_path, name)?,
             key_path: KeyPath::new(&args.output, &args.certificate_path, args.key_path, name)?,
@@ -148,6 +202,31 @@ pub struct Config {
     pub(crate) email: Vec<String>,
     pub production: bool,
     pub port: u16,
+    /// where the challenge servers listen, empty means everywhere
+    pub bind: Vec<IpAddr>,
+    /// use tls-alpn-01 instead of http-01
+    pub tls_alpn: bool,
+    pub tls_port: u16,
+    /// write http challenges here instead of serving them
+    pub webroot: Option<PathBuf>,
+    pub other_requests: OtherRequests,
+    pub key_type: KeyType,
+    pub reuse_key: bool,
+    /// DER encoded, provided by the user. We never see its private key.
+    pub csr: Option<Vec<u8>>,
+    /// issuer common name of the topmost certificate in the chain
+    pub preferred_chain: Option<String>,
+    /// certificate profile to order, if the certificate authority has those
+    pub profile: Option<String>,
+    /// the certificate the new one replaces, for ACME Renewal Information
+    pub replaces: Option<CertificateIdentifier<'static>>,
+    /// how long to wait for the challenges to be validated
+    pub validation_timeout: Duration,
+    /// how long to wait for the certificate to be signed
+    pub finalize_timeout: Duration,
+    pub ca: CaConfig,
+    /// if set prove control over domains using dns
+    pub dns: Option<DnsConfig>,
     pub output_config: OutputConfig,
     /// reload an external systemd service
     pub reload: Option<String>,
@@ -159,17 +238,86 @@ pub struct Config {
     pub diagnostics: diagnostics::Config,
 }
 
+/// Which account the `account` subcommand manages
+#[derive(Debug, Clone)]
+pub struct AccountConfig {
+    pub ca: CaConfig,
+    pub production: bool,
+}
+
+impl AccountConfig {
+    fn new(ca: CaArgs, production: bool) -> eyre::Result<Self> {
+        let ca = CaConfig::new(ca)?;
+        ca.directory(production)?;
+        Ok(AccountConfig { ca, production })
+    }
+}
+
+impl TryFrom<AccountArgs> for AccountConfig {
+    type Error = eyre::Report;
+
+    fn try_from(args: AccountArgs) -> Result<Self, Self::Error> {
+        AccountConfig::new(args.ca, args.production)
+    }
+}
+
+/// Revoking only needs to find the certificate and the account that
+/// requested it
+#[derive(Debug, Clone)]
+pub struct RevokeConfig {
+    pub account: AccountConfig,
+    pub output_config: OutputConfig,
+}
+
+impl TryFrom<RevokeArgs> for RevokeConfig {
+    type Error = eyre::Report;
+
+    fn try_from(args: RevokeArgs) -> Result<Self, Self::Error> {
+        // no dns provider is needed as nothing is validated
+        let domains = domains::validate(args.domain, true)?;
+        let name = name(&domains)?;
+        Ok(RevokeConfig {
+            account: AccountConfig::new(args.ca, args.production)?,
+            output_config: OutputConfig::new(args.output_config, &name)?,
+        })
+    }
+}
+
 impl TryFrom<RenewArgs> for Config {
     type Error = eyre::Report;
 
     fn try_from(args: RenewArgs) -> Result<Self, Self::Error> {
-        let name = name(&args.domain)?;
+        let dns = DnsConfig::new(args.dns)?;
+        let domains = domains::validate(args.domain, dns.is_some())?;
+        let csr = args
+            .csr
+            .map(|path| csr::load(&path, &domains))
+            .transpose()?;
+        let name = name(&domains)?;
         let output_config = OutputConfig::new(args.output_config, &name)?;
+        let ca = CaConfig::new(args.ca)?;
+        // fail early if there is no staging environment to use
+        ca.directory(args.production)?;
         Ok(Config {
-            domains: args.domain,
+            domains,
             email: args.email,
             production: args.production,
             port: args.port,
+            bind: args.bind,
+            tls_alpn: args.tls_alpn,
+            tls_port: args.tls_port,
+            webroot: args.webroot,
+            other_requests: OtherRequests::new(args.upstream, args.redirect_https)?,
+            key_type: args.key_type,
+            reuse_key: args.reuse_key,
+            csr,
+            preferred_chain: args.preferred_chain,
+            profile: args.profile,
+            replaces: None,
+            validation_timeout: Duration::from_secs(args.validation_timeout),
+            finalize_timeout: Duration::from_secs(args.finalize_timeout),
+            ca,
+            dns,
             output_config,
             reload: args.reload,
             force: args.force,
@@ -193,6 +341,24 @@ impl Config {
             email: vec!["test@testdomain.org".into()],
             production: false,
             port,
+            bind: Vec::new(),
+            tls_alpn: false,
+            tls_port: 443,
+            webroot: None,
+            other_requests: OtherRequests::NotFound,
+            key_type: KeyType::default(),
+            reuse_key: false,
+            csr: None,
+            preferred_chain: None,
+            profile: None,
+            replaces: None,
+            validation_timeout: Duration::from_secs(30),
+            finalize_timeout: Duration::from_secs(60),
+            ca: CaConfig {
+                account_dir: dir.to_owned(),
+                ..CaConfig::default()
+            },
+            dns: None,
             output_config,
             reload: None,
             renew_early: false,
@@ -1,9 +1,10 @@
 use clap::{Parser, Subcommand, ValueHint};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use time::macros::format_description;
 
-use super::Output;
+use super::{DnsProviderKind, KeyType, Output, RevocationReason, RevokedFiles, TsigAlgorithm};
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
@@ -13,6 +14,11 @@ pub enum Commands {
     Install(InstallArgs),
     /// Disable and remove renew-certs system service.
     Uninstall,
+    /// Revoke the certificate, for example because its key leaked
+    Revoke(RevokeArgs),
+    /// Inspect or change the account with the certificate authority
+    #[clap(subcommand)]
+    Account(AccountAction),
 }
 
 impl Commands {
@@ -22,10 +28,59 @@ impl Commands {
             Commands::Run(args) => args.debug,
             Commands::Install(args) => args.run.debug,
             Commands::Uninstall => false,
+            Commands::Revoke(args) => args.debug,
+            Commands::Account(action) => action.args().debug,
         }
     }
 }
 
+#[derive(Subcommand, Debug)]
+pub enum AccountAction {
+    /// Show the stored account as the certificate authority knows it
+    Show(AccountArgs),
+    /// Replace the contact info of the account
+    UpdateContact {
+        /// Contact info, leave out to remove all contact info
+        #[clap(long, value_hint = ValueHint::EmailAddress)]
+        email: Vec<String>,
+
+        #[clap(flatten)]
+        account: AccountArgs,
+    },
+    /// Replace the account key with a newly generated one, for example
+    /// because the credentials file leaked
+    KeyRollover(AccountArgs),
+    /// Permanently deactivate the account. Certificates requested with it
+    /// stay valid. A new account is created on the next run.
+    Deactivate(AccountArgs),
+}
+
+impl AccountAction {
+    #[must_use]
+    pub fn args(&self) -> &AccountArgs {
+        match self {
+            AccountAction::Show(args)
+            | AccountAction::KeyRollover(args)
+            | AccountAction::Deactivate(args) => args,
+            AccountAction::UpdateContact { account, .. } => account,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct AccountArgs {
+    /// Manage the account for the production environment of the
+    /// certificate authority instead of the one for its staging environment
+    #[clap(long)]
+    pub production: bool,
+
+    #[clap(long)]
+    pub debug: bool,
+
+    #[clap(flatten)]
+    pub ca: CaArgs,
+}
+
 #[derive(Debug, Clone)]
 pub struct Time(pub time::Time);
 
@@ -49,16 +104,65 @@ pub struct InstallArgs {
     #[clap(long)]
     pub service_name: Option<String>,
 
+    /// Let systemd listen on the challenge port(s) and pass them to renewc.
+    /// The socket only listens while the service runs. Together with
+    /// `--run-as` the service does not need root to use ports below 1025.
+    #[clap(long)]
+    pub socket_activation: bool,
+
+    /// Run the service as this user instead of root. The user needs write
+    /// access to the certificate and account files, and permission to
+    /// reload the `--reload` service.
+    #[clap(long, value_name = "USER")]
+    pub run_as: Option<String>,
+
     #[clap(flatten)]
     pub run: RenewArgs,
 }
 
+#[derive(Parser, Debug)]
+pub struct RevokeArgs {
+    /// Why the certificate is revoked
+    #[clap(long, value_enum, default_value_t = RevocationReason::default())]
+    pub reason: RevocationReason,
+
+    /// Sign the revocation using the certificate's private key instead of
+    /// the ACME account key. Needed if the account that requested the
+    /// certificate is lost.
+    #[clap(long)]
+    pub with_cert_key: bool,
+
+    /// What to do with the certificate files after revoking
+    #[clap(long, value_enum, default_value_t = RevokedFiles::default())]
+    pub files: RevokedFiles,
+
+    /// domain(s) of the certificate, the same as passed to `run`
+    #[clap(long, short, required = true, value_hint=ValueHint::Other)]
+    pub domain: Vec<String>,
+
+    /// Revoke using the production environment of the certificate
+    /// authority, use this for certificates requested with `--production`
+    #[clap(long)]
+    pub production: bool,
+
+    #[clap(long)]
+    pub debug: bool,
+
+    #[clap(flatten)]
+    pub ca: CaArgs,
+
+    #[clap(flatten)]
+    pub output_config: OutputArgs,
+}
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 pub struct RenewArgs {
     /// domain(s) request certificates for multiple subdomains
     /// by passing this argument multiple times with various domains
     /// note the base domain must be the same in all
+    /// an ip address can be passed instead of a domain, note not all
+    /// certificate authorities issue certificates for ip addresses
     #[clap(long, short, required = true, value_hint=ValueHint::Other)]
     pub domain: Vec<String>,
 
@@ -66,8 +170,9 @@ pub struct RenewArgs {
     #[clap(long, value_hint = ValueHint::EmailAddress)]
     pub email: Vec<String>,
 
-    /// Use Let's Encrypt production environment
-    /// (see https://letsencrypt.org/docs/staging-environment/)
+    /// Use the production environment of the certificate authority
+    /// instead of its staging environment. For Let's Encrypt
+    /// see: https://letsencrypt.org/docs/staging-environment/
     #[clap(long)]
     pub production: bool,
 
@@ -76,6 +181,81 @@ pub struct RenewArgs {
     #[clap(long, short, default_value_t = 80, value_parser = clap::value_parser!(u16).range(1..))]
     pub port: u16,
 
+    /// Address to listen on for challenges, pass multiple times to listen
+    /// on several. Use this to only listen on specific interfaces. By
+    /// default all interfaces are used, both IPv6 and IPv4.
+    #[clap(long, value_name = "ADDR")]
+    pub bind: Vec<IpAddr>,
+
+    /// Prove control over the domain(s) using the TLS-ALPN-01 challenge on
+    /// port 443 instead of HTTP on port 80. Use this if only port 443 is
+    /// reachable from the internet.
+    #[clap(long)]
+    pub tls_alpn: bool,
+
+    /// External port 443 should be forwarded to this internal port.
+    /// Only used with `--tls-alpn`.
+    #[clap(long, default_value_t = 443, value_parser = clap::value_parser!(u16).range(1..))]
+    pub tls_port: u16,
+
+    /// Do not start a server, instead write the http challenges to
+    /// `.well-known/acme-challenge/` in this directory. Use this when a web
+    /// server such as nginx or Apache already serves this directory on port 80.
+    #[clap(long, value_hint = ValueHint::DirPath, conflicts_with = "tls_alpn")]
+    pub webroot: Option<PathBuf>,
+
+    /// Forward requests that are not for a challenge to this server, for
+    /// example `http://127.0.0.1:8080`. Use this to briefly take over port
+    /// 80 on a live host without the site going down.
+    #[clap(long, value_name = "URL", conflicts_with_all = ["webroot", "tls_alpn"])]
+    pub upstream: Option<http::Uri>,
+
+    /// Redirect requests that are not for a challenge to https
+    #[clap(long, conflicts_with_all = ["webroot", "tls_alpn", "upstream"])]
+    pub redirect_https: bool,
+
+    /// Algorithm and size of the private key to generate for the
+    /// certificate. Use RSA for clients that do not support ECDSA.
+    #[clap(long, value_enum, default_value_t = KeyType::default())]
+    pub key_type: KeyType,
+
+    /// Keep using the private key of the existing certificate instead of
+    /// generating a new one. Useful if the key is pinned, for example using
+    /// DANE/TLSA records. If the key can not be loaded a new key of
+    /// `--key-type` is generated.
+    #[clap(long)]
+    pub reuse_key: bool,
+
+    /// Use this certificate signing request (PEM or DER) instead of
+    /// generating a key. It must be for exactly the domains passed using
+    /// `--domain`. Only the certificate and chain are stored as the private
+    /// key never leaves wherever the CSR was made.
+    #[clap(long, value_hint = ValueHint::FilePath, conflicts_with_all = ["reuse_key", "key_type"])]
+    pub csr: Option<PathBuf>,
+
+    /// Certificate authorities can offer multiple certificate chains. Pick
+    /// the one whose topmost certificate is issued by this common name,
+    /// for example "ISRG Root X1". Uses the default chain if none match.
+    #[clap(long, value_name = "ISSUER CN")]
+    pub preferred_chain: Option<String>,
+
+    /// Request the certificate using this profile of the certificate
+    /// authority, for example "shortlived" or "tlsserver". Which profiles
+    /// exist and what they mean differs per certificate authority.
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Seconds to wait for the certificate authority to validate the
+    /// challenges. Until then the order is checked with exponential backoff
+    /// or as often as the certificate authority asks.
+    #[clap(long, default_value_t = 30, value_name = "SECONDS")]
+    pub validation_timeout: u64,
+
+    /// Seconds to wait for the certificate authority to sign the
+    /// certificate once the challenges are validated
+    #[clap(long, default_value_t = 60, value_name = "SECONDS")]
+    pub finalize_timeout: u64,
+
     /// Systemd service to reload after renewal
     #[clap(long, short, value_hint=ValueHint::Other)]
     pub reload: Option<String>,
@@ -96,11 +276,127 @@ pub struct RenewArgs {
     #[clap(long)]
     pub debug: bool,
 
+    #[clap(flatten)]
+    pub ca: CaArgs,
+
+    #[clap(flatten)]
+    pub dns: DnsArgs,
+
     // the options in the Output struct are added at the end
     #[clap(flatten)]
     pub output_config: OutputArgs,
 }
 
+#[derive(Parser, Debug, Clone, Default)]
+pub struct CaArgs {
+    /// Directory url of the ACME certificate authority to use. If left
+    /// unspecified Let's Encrypt is used.
+    ///
+    /// Note: The staging environment is only known for Let's Encrypt. For
+    /// other certificate authorities pass it using `--acme-staging-directory`.
+    #[clap(long, value_hint=ValueHint::Url)]
+    pub acme_directory: Option<String>,
+
+    /// Directory url of the staging environment of the certificate authority
+    /// set with `--acme-directory`. Leave it out if the certificate
+    /// authority has no staging environment.
+    #[clap(long, value_hint=ValueHint::Url, requires = "acme_directory")]
+    pub acme_staging_directory: Option<String>,
+
+    /// PEM file containing the root certificate the ACME server's https
+    /// certificate is signed with. Only needed if that is not a publicly
+    /// trusted root, for example with a local Pebble or step-ca instance.
+    #[clap(long, value_hint=ValueHint::FilePath)]
+    pub acme_root: Option<PathBuf>,
+
+    /// Directory in which the ACME account credentials are kept. An account
+    /// is created once for each certificate authority and reused on every
+    /// following run.
+    ///
+    /// If left unspecified this is `/var/lib/renewc` when running as root
+    /// and `$XDG_STATE_HOME/renewc` (usually `~/.local/state/renewc`)
+    /// otherwise.
+    #[clap(long, value_hint=ValueHint::DirPath)]
+    pub account_dir: Option<PathBuf>,
+
+    /// Key identifier for External Account Binding (EAB). Some certificate
+    /// authorities, like ZeroSSL and Google Trust Services, require this to
+    /// create an account. Use together with `--eab-hmac-key` or
+    /// `--eab-hmac-key-file`.
+    #[clap(long, value_hint=ValueHint::Other)]
+    pub eab_kid: Option<String>,
+
+    /// HMAC key for External Account Binding (EAB) as given by the
+    /// certificate authority (base64url encoded).
+    ///
+    /// Note: this is never passed on to the service set up by `install`,
+    /// use `--eab-hmac-key-file` for that.
+    #[clap(
+        long,
+        env = "RENEWC_EAB_HMAC_KEY",
+        hide_env_values = true,
+        requires = "eab_kid",
+        conflicts_with = "eab_hmac_key_file",
+        value_hint = ValueHint::Other
+    )]
+    pub eab_hmac_key: Option<String>,
+
+    /// File containing the HMAC key for External Account Binding (EAB)
+    #[clap(long, requires = "eab_kid", value_hint=ValueHint::FilePath)]
+    pub eab_hmac_key_file: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct DnsArgs {
+    /// Prove control over the domain(s) using DNS (dns-01 challenge) instead
+    /// of over HTTP. The provider is used to create the TXT records the
+    /// certificate authority checks. No port has to be reachable from the
+    /// internet.
+    ///
+    /// Note: HTTP is still used for domains the certificate authority does
+    /// not offer a dns challenge for.
+    #[clap(long, value_enum)]
+    pub dns_provider: Option<DnsProviderKind>,
+
+    /// Address of the authoritative DNS server to send RFC 2136 updates to.
+    /// The port defaults to 53.
+    #[clap(long, required_if_eq("dns_provider", "rfc2136"), value_hint=ValueHint::Hostname)]
+    pub rfc2136_server: Option<String>,
+
+    /// Zone the records are updated in, for example `example.org`
+    #[clap(long, required_if_eq("dns_provider", "rfc2136"), value_hint=ValueHint::Other)]
+    pub rfc2136_zone: Option<String>,
+
+    /// Name of the TSIG key used to sign the updates
+    #[clap(long, required_if_eq("dns_provider", "rfc2136"), value_hint=ValueHint::Other)]
+    pub rfc2136_key_name: Option<String>,
+
+    /// Algorithm of the TSIG key
+    #[clap(long, value_enum, default_value_t = TsigAlgorithm::HmacSha256)]
+    pub rfc2136_key_algorithm: TsigAlgorithm,
+
+    /// File containing the base64 encoded TSIG secret. A key file as
+    /// generated by `tsig-keygen` works too.
+    #[clap(long, required_if_eq("dns_provider", "rfc2136"), value_hint=ValueHint::FilePath)]
+    pub rfc2136_key_file: Option<PathBuf>,
+
+    /// Command that creates the TXT record for a dns challenge. It is run
+    /// using `sh -c` with these environment variables set:
+    ///  - `RENEWC_DOMAIN`: the domain being validated
+    ///  - `RENEWC_RECORD_NAME`: name of the record, `_acme-challenge.<domain>`
+    ///  - `RENEWC_TXT_VALUE`: the value the TXT record should have
+    ///
+    /// The record should be resolvable once the command exits. It is killed
+    /// if it takes longer than five minutes.
+    #[clap(long, required_if_eq("dns_provider", "exec"), value_hint=ValueHint::CommandString)]
+    pub dns_present_hook: Option<String>,
+
+    /// Command that removes the TXT record after validation. It gets the
+    /// same environment variables as `--dns-present-hook`.
+    #[clap(long, value_hint=ValueHint::CommandString)]
+    pub dns_cleanup_hook: Option<String>,
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct OutputArgs {
     /// How to store the output, encoding and ways to split
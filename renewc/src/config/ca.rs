@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs};
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use instant_acme::LetsEncrypt;
+
+use super::args::CaArgs;
+
+/// The ACME certificate authority (CA) to request certificates from
+#[derive(Debug, Clone)]
+pub struct CaConfig {
+    pub directory: String,
+    /// Not every CA has a staging environment
+    pub staging_directory: Option<String>,
+    /// Root certificate for the CA's https server if it is not publicly trusted
+    pub root: Option<PathBuf>,
+    /// Where the account credentials for each CA are stored
+    pub account_dir: PathBuf,
+    /// External account binding, required by some CAs to create an account
+    pub eab: Option<Eab>,
+}
+
+#[derive(Clone)]
+pub struct Eab {
+    pub kid: String,
+    pub hmac_key: Vec<u8>,
+}
+
+// keep the key out of debug output and traces
+impl fmt::Debug for Eab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Eab")
+            .field("kid", &self.kid)
+            .field("hmac_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Eab {
+    fn new(
+        kid: Option<String>,
+        hmac_key: Option<String>,
+        hmac_key_file: Option<&Path>,
+    ) -> eyre::Result<Option<Self>> {
+        let hmac_key = match (hmac_key, hmac_key_file) {
+            (Some(key), _) => key,
+            (None, Some(path)) => fs::read_to_string(path)
+                .wrap_err("Could not read external account binding HMAC key")
+                .with_note(|| format!("path: {path:?}"))?,
+            (None, None) => {
+                if kid.is_some() {
+                    return Err(eyre::eyre!(
+                        "External account binding needs a HMAC key as well as a key id"
+                    ))
+                    .suggestion("Pass `--eab-hmac-key` or `--eab-hmac-key-file`");
+                }
+                return Ok(None);
+            }
+        };
+        let kid = kid.expect("clap enforces eab_kid is set with hmac key");
+
+        Ok(Some(Self {
+            kid,
+            hmac_key: decode_hmac_key(&hmac_key)?,
+        }))
+    }
+}
+
+/// CA's hand out the key base64url encoded, some include padding or use the
+/// standard alphabet.
+fn decode_hmac_key(key: &str) -> eyre::Result<Vec<u8>> {
+    let key = key.trim();
+    BASE64URL_NOPAD
+        .decode(key.trim_end_matches('=').as_bytes())
+        .or_else(|_| BASE64.decode(key.as_bytes()))
+        .wrap_err("External account binding HMAC key is not valid base64")
+        .suggestion("Pass the key exactly as given by the certificate authority")
+}
+
+fn default_account_dir() -> PathBuf {
+    if libproc::libproc::proc_pid::am_root() {
+        return PathBuf::from("/var/lib/renewc");
+    }
+
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+    match state_home {
+        Some(dir) => dir.join("renewc"),
+        None => PathBuf::from(".renewc"),
+    }
+}
+
+impl Default for CaConfig {
+    fn default() -> Self {
+        Self {
+            directory: LetsEncrypt::Production.url().to_owned(),
+            staging_directory: Some(LetsEncrypt::Staging.url().to_owned()),
+            root: None,
+            account_dir: default_account_dir(),
+            eab: None,
+        }
+    }
+}
+
+impl CaConfig {
+    pub(super) fn new(args: CaArgs) -> eyre::Result<Self> {
+        let CaArgs {
+            acme_directory,
+            acme_staging_directory,
+            acme_root,
+            account_dir,
+            eab_kid,
+            eab_hmac_key,
+            eab_hmac_key_file,
+        } = args;
+
+        let account_dir = account_dir.unwrap_or_else(default_account_dir);
+        let eab = Eab::new(eab_kid, eab_hmac_key, eab_hmac_key_file.as_deref())?;
+        Ok(match acme_directory {
+            Some(directory) => Self {
+                directory,
+                staging_directory: acme_staging_directory,
+                root: acme_root,
+                account_dir,
+                eab,
+            },
+            None => Self {
+                root: acme_root,
+                account_dir,
+                eab,
+                ..Self::default()
+            },
+        })
+    }
+
+    #[must_use]
+    pub fn has_staging(&self) -> bool {
+        self.staging_directory.is_some()
+    }
+
+    /// the directory url for the production or staging environment
+    pub fn directory(&self, production: bool) -> eyre::Result<&str> {
+        if production {
+            return Ok(&self.directory);
+        }
+
+        self.staging_directory
+            .as_deref()
+            .ok_or_else(|| eyre::eyre!("The certificate authority has no staging environment"))
+            .with_note(|| format!("certificate authority: {}", self.directory))
+            .suggestion(
+                "Request a production certificate using `--production` or pass \
+                the staging environment using `--acme-staging-directory`",
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_key_encodings() {
+        let raw = vec![0xfb, 0xff, 0x01, 0x02];
+        assert_eq!(decode_hmac_key("-_8BAg").unwrap(), raw);
+        assert_eq!(decode_hmac_key("-_8BAg==\n").unwrap(), raw);
+        assert_eq!(decode_hmac_key("+/8BAg==").unwrap(), raw);
+        assert!(decode_hmac_key("not base64!").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::extensions::ParsedExtension;
+use x509_parser::prelude::FromDer;
+
+use crate::cert::info::unwrap_name;
+
+/// The names a CSR asks to be certified. These are the subject alternative
+/// names, or if there are none the subject common name.
+fn identifiers(csr: &X509CertificationRequest) -> Vec<String> {
+    let sans: Vec<_> = csr
+        .requested_extensions()
+        .into_iter()
+        .flatten()
+        .filter_map(|ext| match ext {
+            ParsedExtension::SubjectAlternativeName(san) => Some(&san.general_names),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(unwrap_name)
+        .collect();
+    if !sans.is_empty() {
+        return sans;
+    }
+
+    csr.certification_request_info
+        .subject
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn to_der(bytes: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    if !bytes.trim_ascii_start().starts_with(b"-----BEGIN") {
+        return Ok(bytes);
+    }
+
+    let pem = pem::parse(bytes).wrap_err("Not a valid PEM file")?;
+    match pem.tag() {
+        "CERTIFICATE REQUEST" | "NEW CERTIFICATE REQUEST" => Ok(pem.into_contents()),
+        other => Err(eyre::eyre!("PEM file contains a {other}"))
+            .suggestion("Pass a file containing a `CERTIFICATE REQUEST`"),
+    }
+}
+
+/// Reads a PEM or DER encoded CSR and checks it requests exactly the
+/// `domains`. Returns the CSR DER encoded.
+pub(super) fn load(path: &Path, domains: &[String]) -> eyre::Result<Vec<u8>> {
+    let bytes = fs::read(path)
+        .wrap_err("Could not read CSR")
+        .with_note(|| format!("path: {path:?}"))?;
+    let der = to_der(bytes)
+        .wrap_err("Could not decode CSR")
+        .with_note(|| format!("path: {path:?}"))?;
+    let (_, csr) = X509CertificationRequest::from_der(&der)
+        .wrap_err("Could not parse CSR")
+        .with_note(|| format!("path: {path:?}"))?;
+
+    let in_csr: BTreeSet<_> = identifiers(&csr).into_iter().collect();
+    let requested: BTreeSet<_> = domains.iter().cloned().collect();
+    if in_csr != requested {
+        return Err(eyre::eyre!("CSR is not for the requested domains"))
+            .with_note(|| format!("CSR is for: {in_csr:?}"))
+            .with_note(|| format!("requested domains: {requested:?}"))
+            .suggestion("Pass every name in the CSR using `--domain` or ask for a new CSR");
+    }
+
+    Ok(der)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{Certificate, CertificateParams};
+
+    fn csr(names: &[&str]) -> Certificate {
+        let names = names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        Certificate::from_params(CertificateParams::new(names)).unwrap()
+    }
+
+    #[test]
+    fn pem_and_der() {
+        let dir = tempfile::tempdir().unwrap();
+        let domains = ["example.org".to_owned(), "www.example.org".to_owned()];
+        let cert = csr(&["www.example.org", "Example.org"]);
+
+        let der = cert.serialize_request_der().unwrap();
+
+        let path = dir.path().join("csr.pem");
+        let pem = pem::Pem::new("CERTIFICATE REQUEST", der.clone());
+        fs::write(&path, pem::encode(&pem)).unwrap();
+        assert_eq!(load(&path, &domains).unwrap(), der);
+
+        let path = dir.path().join("csr.der");
+        fs::write(&path, &der).unwrap();
+        assert_eq!(load(&path, &domains).unwrap(), der);
+    }
+
+    #[test]
+    fn domains_must_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("csr.pem");
+        let cert = csr(&["example.org", "mail.example.org"]);
+        fs::write(&path, cert.serialize_request_pem().unwrap()).unwrap();
+
+        let err = load(&path, &["example.org".to_owned()]).unwrap_err();
+        assert_eq!(err.to_string(), "CSR is not for the requested domains");
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use data_encoding::BASE64;
+
+use super::args::DnsArgs;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsProviderKind {
+    /// Print the TXT records that need to be created and wait until
+    /// you have done so. Only works when running interactively.
+    Manual,
+    /// Send TSIG signed dynamic updates (RFC 2136) to an authoritative
+    /// DNS server such as BIND or Knot. Configure using the
+    /// `--rfc2136-*` options.
+    Rfc2136,
+    /// Run your own commands to create and remove the records. Configure
+    /// them using `--dns-present-hook` and `--dns-cleanup-hook`.
+    Exec,
+}
+
+impl Display for DnsProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DnsProviderKind::Manual => f.write_str("manual"),
+            DnsProviderKind::Rfc2136 => f.write_str("rfc2136"),
+            DnsProviderKind::Exec => f.write_str("exec"),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TsigAlgorithm {
+    HmacSha1,
+    #[default]
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Display for TsigAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TsigAlgorithm::HmacSha1 => f.write_str("hmac-sha1"),
+            TsigAlgorithm::HmacSha256 => f.write_str("hmac-sha256"),
+            TsigAlgorithm::HmacSha384 => f.write_str("hmac-sha384"),
+            TsigAlgorithm::HmacSha512 => f.write_str("hmac-sha512"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rfc2136Config {
+    pub server: String,
+    pub zone: String,
+    pub key_name: String,
+    pub key_algorithm: TsigAlgorithm,
+    pub key_secret: Vec<u8>,
+}
+
+// keep the secret out of debug output and traces
+impl fmt::Debug for Rfc2136Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rfc2136Config")
+            .field("server", &self.server)
+            .field("zone", &self.zone)
+            .field("key_name", &self.key_name)
+            .field("key_algorithm", &self.key_algorithm)
+            .field("key_secret", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecConfig {
+    pub present: String,
+    pub cleanup: Option<String>,
+}
+
+/// Accepts a file with only the base64 secret or a BIND style key file
+/// in which the secret is set using: `secret "<base64>";`
+fn read_tsig_secret(path: &Path) -> eyre::Result<Vec<u8>> {
+    let content = fs::read_to_string(path)
+        .wrap_err("Could not read TSIG key file")
+        .with_note(|| format!("path: {path:?}"))?;
+
+    let secret = match content.split_once("secret") {
+        Some((_, rest)) => rest.split('"').nth(1).unwrap_or_default(),
+        None => content.trim(),
+    };
+    BASE64
+        .decode(secret.as_bytes())
+        .wrap_err("TSIG secret is not valid base64")
+        .with_note(|| format!("path: {path:?}"))
+}
+
+/// Labels are at most 63 bytes and the whole name at most 255 bytes in
+/// the wire format (RFC 1035 section 2.3.4)
+pub(crate) fn check_dns_name(name: &str) -> eyre::Result<()> {
+    let mut wire_len = 1; // the root label
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return Err(eyre::eyre!("DNS name has a label longer than 63 bytes"))
+                .with_note(|| format!("name: {name}"))
+                .with_note(|| format!("label: {label}"));
+        }
+        wire_len += 1 + label.len();
+    }
+    if wire_len > 255 {
+        return Err(eyre::eyre!("DNS name is longer than 255 bytes"))
+            .with_note(|| format!("name: {name}"));
+    }
+    Ok(())
+}
+
+/// How to create the TXT records for dns-01 challenges
+#[derive(Debug, Clone)]
+pub enum DnsConfig {
+    Manual,
+    Rfc2136(Rfc2136Config),
+    Exec(ExecConfig),
+}
+
+impl DnsConfig {
+    pub(super) fn new(args: DnsArgs) -> eyre::Result<Option<Self>> {
+        let DnsArgs {
+            dns_provider,
+            rfc2136_server,
+            rfc2136_zone,
+            rfc2136_key_name,
+            rfc2136_key_algorithm,
+            rfc2136_key_file,
+            dns_present_hook,
+            dns_cleanup_hook,
+        } = args;
+
+        let Some(dns_provider) = dns_provider else {
+            return Ok(None);
+        };
+        Ok(Some(match dns_provider {
+            DnsProviderKind::Manual => Self::Manual,
+            DnsProviderKind::Rfc2136 => {
+                let key_file = rfc2136_key_file.expect("clap requires it for rfc2136");
+                let zone = rfc2136_zone.expect("clap requires it for rfc2136");
+                check_dns_name(&zone).wrap_err("Invalid `--rfc2136-zone`")?;
+                let key_name = rfc2136_key_name.expect("clap requires it for rfc2136");
+                check_dns_name(&key_name).wrap_err("Invalid `--rfc2136-key-name`")?;
+                Self::Rfc2136(Rfc2136Config {
+                    server: rfc2136_server.expect("clap requires it for rfc2136"),
+                    zone,
+                    key_name,
+                    key_algorithm: rfc2136_key_algorithm,
+                    key_secret: read_tsig_secret(&key_file)?,
+                })
+            }
+            DnsProviderKind::Exec => Self::Exec(ExecConfig {
+                present: dns_present_hook.expect("clap requires it for exec"),
+                cleanup: dns_cleanup_hook,
+            }),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("renewc.key");
+        fs::write(
+            &path,
+            "key \"renewc\" {\n\talgorithm hmac-sha256;\n\tsecret \"AQIDBA==\";\n};\n",
+        )
+        .unwrap();
+        assert_eq!(read_tsig_secret(&path).unwrap(), vec![1, 2, 3, 4]);
+
+        fs::write(&path, "AQIDBA==\n").unwrap();
+        assert_eq!(read_tsig_secret(&path).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn dns_name_limits() {
+        assert!(check_dns_name("_acme-challenge.example.org.").is_ok());
+        assert!(check_dns_name(&format!("{}.org", "a".repeat(63))).is_ok());
+        assert!(check_dns_name(&format!("{}.org", "a".repeat(64))).is_err());
+        assert!(check_dns_name(&format!("{}.org", "a".repeat(300))).is_err());
+
+        let label = "a".repeat(63);
+        let long = [label.as_str(); 4].join(".");
+        assert!(check_dns_name(&long).is_err(), "{} bytes", long.len() + 2);
+    }
+}
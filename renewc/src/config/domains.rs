@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+
+use color_eyre::eyre;
+use color_eyre::Help;
+
+fn normalize(domain: &str) -> eyre::Result<String> {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    if domain.is_empty() {
+        return Err(eyre::eyre!("Domain can not be empty"));
+    }
+    // ip addresses are certified as is (RFC 8738), brackets around an ipv6
+    // address are allowed so it can be copied from an url
+    let unbracketed = domain.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return Ok(ip.to_string());
+    }
+
+    let Some(base) = domain.strip_prefix("*.") else {
+        if domain.contains('*') {
+            return Err(eyre::eyre!("Invalid wildcard domain: {domain}"))
+                .note("A wildcard (*) may only be the first label, e.g. `*.example.org`");
+        }
+        return Ok(domain);
+    };
+
+    if base.contains('*') {
+        return Err(eyre::eyre!("Invalid wildcard domain: {domain}"))
+            .note("A wildcard (*) may only be the first label, e.g. `*.example.org`");
+    }
+    if !base.contains('.') {
+        return Err(eyre::eyre!(
+            "Wildcard domain must be below a second level domain: {domain}"
+        ))
+        .note("For example `*.example.org` is fine but `*.org` is not");
+    }
+    Ok(domain)
+}
+
+/// Normalizes the domains (lowercase, no trailing dot) and checks any
+/// wildcard domains are valid and can be validated. Ip addresses are
+/// normalized to their canonical form.
+pub(super) fn validate(domains: Vec<String>, dns_provider: bool) -> eyre::Result<Vec<String>> {
+    let domains = domains
+        .iter()
+        .map(String::as_str)
+        .map(normalize)
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    if let Some(wildcard) = domains.iter().find(|d| d.starts_with("*.")) {
+        if !dns_provider {
+            return Err(eyre::eyre!(
+                "Wildcard domains can only be validated using dns"
+            ))
+            .with_note(|| format!("wildcard domain: {wildcard}"))
+            .suggestion("Pick a dns provider using `--dns-provider`");
+        }
+    }
+
+    Ok(domains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| (*d).to_owned()).collect()
+    }
+
+    #[test]
+    fn wildcards() {
+        let valid = validate(domains(&["*.Example.org.", "example.org"]), true).unwrap();
+        assert_eq!(valid, domains(&["*.example.org", "example.org"]));
+
+        assert!(validate(domains(&["*.example.org"]), false).is_err());
+        assert!(validate(domains(&["*.org"]), true).is_err());
+        assert!(validate(domains(&["www.*.example.org"]), true).is_err());
+        assert!(validate(domains(&["*.*.example.org"]), true).is_err());
+    }
+
+    #[test]
+    fn ip_addresses() {
+        let valid = validate(
+            domains(&["192.0.2.1", "[2001:DB8:0::1]", "example.org"]),
+            false,
+        )
+        .unwrap();
+        assert_eq!(valid, domains(&["192.0.2.1", "2001:db8::1", "example.org"]));
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt::{self, Display};
+
+/// Algorithm (and size) of the private key of the certificate
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyType {
+    #[default]
+    EcdsaP256,
+    EcdsaP384,
+    Rsa2048,
+    Rsa3072,
+    Rsa4096,
+    /// Not supported by every certificate authority, Let's Encrypt for
+    /// example does not accept it
+    Ed25519,
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyType::EcdsaP256 => f.write_str("ecdsa-p256"),
+            KeyType::EcdsaP384 => f.write_str("ecdsa-p384"),
+            KeyType::Rsa2048 => f.write_str("rsa-2048"),
+            KeyType::Rsa3072 => f.write_str("rsa-3072"),
+            KeyType::Rsa4096 => f.write_str("rsa-4096"),
+            KeyType::Ed25519 => f.write_str("ed25519"),
+        }
+    }
+}
+
+impl KeyType {
+    pub(crate) fn rsa_bits(self) -> Option<usize> {
+        match self {
+            KeyType::Rsa2048 => Some(2048),
+            KeyType::Rsa3072 => Some(3072),
+            KeyType::Rsa4096 => Some(4096),
+            _ => None,
+        }
+    }
+}
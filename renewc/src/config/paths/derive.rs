@@ -1,6 +1,7 @@
 use color_eyre::eyre::{self, OptionExt};
 use color_eyre::Section;
 use itertools::Itertools;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use tracing::instrument;
 
@@ -12,6 +13,8 @@ pub(crate) fn derive_path(path: &Path, name: &str, ty: &str, extension: &str) ->
 }
 
 fn second_and_top_level_domain(full_domain: &str) -> eyre::Result<&str> {
+    // a wildcard can not end up in the name, it is not allowed in paths
+    let full_domain = full_domain.trim_start_matches("*.");
     let last_dot = full_domain
         .rfind('.')
         .ok_or_eyre("domain has no top level domain [org/net/com etc]")
@@ -31,11 +34,20 @@ fn test_second_and_top_level_domain() {
     assert_eq!(outputs, ["davidsk.dev", "davidsk.dev", "davidsk.dev"]);
 }
 
+/// ip addresses are used whole, the colons in an ipv6 address are not
+/// allowed in paths on every platform
+fn name_part(domain: &str) -> eyre::Result<String> {
+    match domain.parse::<IpAddr>() {
+        Ok(ip) => Ok(ip.to_string().replace(':', "_")),
+        Err(_) => second_and_top_level_domain(domain).map(str::to_owned),
+    }
+}
+
 pub fn name(domains: &[impl AsRef<str>]) -> eyre::Result<String> {
     let mut name_parts = domains
         .iter()
         .map(AsRef::as_ref)
-        .map(second_and_top_level_domain)
+        .map(name_part)
         .collect::<Result<Vec<_>, _>>()?;
     name_parts.sort_unstable();
     name_parts.dedup();
@@ -81,4 +93,21 @@ mod tests {
 
         assert_eq!(name(&domains).unwrap(), "example.org+nm.org");
     }
+
+    #[test]
+    fn wildcard_name() {
+        assert_eq!(name(&["*.example.org"]).unwrap(), "example.org");
+        assert_eq!(
+            name(&["*.example.org", "example.org", "*.sub.nm.org"]).unwrap(),
+            "example.org+nm.org"
+        );
+    }
+
+    #[test]
+    fn ip_name() {
+        assert_eq!(
+            name(&["192.0.2.1", "2001:db8::1", "www.example.org"]).unwrap(),
+            "192.0.2.1+2001_db8__1+example.org"
+        );
+    }
 }
@@ -0,0 +1,53 @@
+use std::fmt::{self, Display};
+
+/// The reasons certificate authorities such as Let's Encrypt accept
+/// (RFC 5280 section 5.3.1)
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RevocationReason {
+    #[default]
+    Unspecified,
+    /// The private key leaked
+    KeyCompromise,
+    AffiliationChanged,
+    /// The certificate has been replaced
+    Superseded,
+    /// The domain(s) are no longer used
+    CessationOfOperation,
+}
+
+impl RevocationReason {
+    #[must_use]
+    pub fn code(self) -> u8 {
+        match self {
+            RevocationReason::Unspecified => 0,
+            RevocationReason::KeyCompromise => 1,
+            RevocationReason::AffiliationChanged => 3,
+            RevocationReason::Superseded => 4,
+            RevocationReason::CessationOfOperation => 5,
+        }
+    }
+}
+
+impl Display for RevocationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevocationReason::Unspecified => f.write_str("unspecified"),
+            RevocationReason::KeyCompromise => f.write_str("key-compromise"),
+            RevocationReason::AffiliationChanged => f.write_str("affiliation-changed"),
+            RevocationReason::Superseded => f.write_str("superseded"),
+            RevocationReason::CessationOfOperation => f.write_str("cessation-of-operation"),
+        }
+    }
+}
+
+/// What to do with the certificate files after revoking
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RevokedFiles {
+    /// Leave them as they are
+    #[default]
+    Keep,
+    /// Add `.revoked` to the file names
+    Rename,
+    /// Remove them
+    Delete,
+}
@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::string::ToString;
 
 use color_eyre::{Help, Report};
@@ -5,6 +6,7 @@ use itertools::Itertools;
 
 mod applications;
 mod port;
+pub(crate) mod problem;
 pub mod reachable;
 pub use applications::Config;
 
@@ -19,11 +21,16 @@ fn insufficent_permission(port: u16) -> bool {
     port <= 1024 && !root()
 }
 
-pub(crate) fn cant_bind_port(config: &config::Config, e: std::io::Error) -> Report {
-    match build_report(&config.diagnostics, e, config.port) {
+pub(crate) fn cant_bind_port(
+    config: &config::Config,
+    addr: SocketAddr,
+    e: std::io::Error,
+) -> Report {
+    let report = match build_report(&config.diagnostics, e, addr.port()) {
         Ok(r) => r,
         Err(r) => r.wrap_err("Could not deduce cause of error"),
-    }
+    };
+    report.wrap_err(format!("Could not listen on {addr}"))
 }
 
 fn build_report<E>(config: &Config, e: E, port: u16) -> Result<Report, Report>
@@ -34,6 +41,10 @@ where
     if insufficent_permission(port) {
         r = r.wrap_err("insufficient permission to attach to port");
         r = r.with_suggestion(|| "You normally need sudo to attach to ports below 1025");
+        r = r.with_suggestion(|| {
+            "Alternatively let systemd listen on the port and pass it to renewc, \
+            `renewc install --socket-activation --run-as <user>` sets that up"
+        });
         r = r.with_note(|| format!("port: {port}"));
     }
 
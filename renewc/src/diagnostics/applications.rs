@@ -39,6 +39,9 @@ impl Config {
     }
 }
 
+/// Can serve the challenges from a directory, see `--webroot`
+const WEB_SERVERS: [&str; 5] = ["nginx", "apache2", "httpd", "lighttpd", "caddy"];
+
 const APPS: [App; 1] = [App {
     name: "haproxy",
     reporter: &haproxy::report,
@@ -76,5 +79,17 @@ pub(super) fn improve_report(
         }
     }
 
+    if let Some(user) = users
+        .iter()
+        .find(|u| WEB_SERVERS.contains(&u.name.to_lowercase().trim()))
+    {
+        report = report.with_suggestion(|| {
+            format!(
+                "{} is a web server, let it serve the challenges by passing its document root to `--webroot`",
+                user.name
+            )
+        });
+    }
+
     report
 }
@@ -0,0 +1,182 @@
+use std::time::{Duration, SystemTime};
+
+use color_eyre::{Help, Report};
+use instant_acme::{self as acme, Problem};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::config::Config;
+use crate::renew::client::RetryAfter;
+
+const PREFIX: &str = "urn:ietf:params:acme:error:";
+
+/// The problem document (RFC 8555 section 6.7) that caused the error, if
+/// the certificate authority sent one
+pub(crate) fn find_problem(report: &Report) -> Option<&Problem> {
+    report.chain().find_map(|e| {
+        e.downcast_ref::<Problem>()
+            .or_else(|| match e.downcast_ref::<acme::Error>() {
+                Some(acme::Error::Api(problem)) => Some(problem),
+                _ => None,
+            })
+    })
+}
+
+/// Problem types without prefix of the problem and its subproblems
+fn problem_types(problem: &Problem) -> Vec<&str> {
+    problem
+        .r#type
+        .iter()
+        .chain(problem.subproblems.iter().filter_map(|p| p.r#type.as_ref()))
+        .map(|ty| ty.trim_start_matches(PREFIX))
+        .collect()
+}
+
+/// When the rate limit resets, Retry-After is either a number of seconds or
+/// a http date.
+fn resets_at(retry_after: &RetryAfter) -> String {
+    let Ok(seconds) = retry_after.value.parse() else {
+        return retry_after.value.clone();
+    };
+    let at = retry_after.received + Duration::from_secs(seconds);
+    let left = at.duration_since(SystemTime::now()).unwrap_or_default();
+    let format = format_description!("[year]-[month]-[day] [hour]:[minute]:[second] UTC");
+    let at = OffsetDateTime::from(at)
+        .format(&format)
+        .expect("format is valid for any date");
+    format!("{at} (in {} minutes)", left.as_secs().div_ceil(60))
+}
+
+fn rate_limited(config: &Config, report: Report, retry_after: Option<&RetryAfter>) -> Report {
+    let report = report
+        .wrap_err("Hit a rate limit of the certificate authority")
+        .note("The error above describes which limit was hit");
+    let report = match retry_after {
+        Some(retry_after) => {
+            report.with_note(|| format!("The limit resets at: {}", resets_at(retry_after)))
+        }
+        None => report,
+    };
+    let report = if config.production {
+        report.suggestion(
+            "Try things out against the staging environment by leaving out \
+            `--production`, its limits are far higher",
+        )
+    } else {
+        report
+    };
+    report.suggestion(
+        "Use `--renew-early` and `--force` sparingly, every certificate \
+        requested counts towards the limits",
+    )
+}
+
+/// Tailored advice for the problem documents of the certificate authority
+pub(crate) fn improve_report(
+    config: &Config,
+    report: Report,
+    retry_after: Option<&RetryAfter>,
+) -> Report {
+    let Some(problem) = find_problem(&report) else {
+        return report;
+    };
+    let types = problem_types(problem);
+    let is = |ty: &str| types.contains(&ty);
+
+    if is("rateLimited") {
+        rate_limited(config, report, retry_after)
+    } else if is("badNonce") {
+        report
+            .wrap_err("The certificate authority rejected our request as a replay")
+            .suggestion("This is usually temporary, try again in a moment")
+    } else if is("caa") {
+        report
+            .wrap_err("A CAA record forbids the certificate authority to issue for the domain")
+            .suggestion(
+                "Add a CAA record allowing the certificate authority, for Let's Encrypt: \
+                `0 issue \"letsencrypt.org\"`. Or remove all CAA records of the domain",
+            )
+    } else if is("dns") {
+        report
+            .wrap_err("The certificate authority could not look up the domain")
+            .suggestion(
+                "Check the domain's A/AAAA records, or with a dns challenge its TXT \
+                records, resolve using a public dns server. For example using `dig`",
+            )
+    } else if is("connection") {
+        report
+            .wrap_err("The certificate authority could not connect to this machine")
+            .suggestion(if config.tls_alpn {
+                "Check external port 443 is forwarded to `--tls-port` on this machine and no firewall blocks it"
+            } else {
+                "Check external port 80 is forwarded to `--port` on this machine and no firewall blocks it"
+            })
+    } else if is("unauthorized") {
+        report
+            .wrap_err("The certificate authority did not get the expected challenge response")
+            .suggestion(
+                "Another server might be answering for the domain, check the domain \
+                points to this machine",
+            )
+    } else {
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre;
+    use std::path::Path;
+
+    fn problem(json: &str) -> Report {
+        let problem: Problem = serde_json::from_str(json).unwrap();
+        Report::new(acme::Error::Api(problem)).wrap_err("Could not create order")
+    }
+
+    #[test]
+    fn rate_limited_report() {
+        let report = problem(
+            r#"{"type":"urn:ietf:params:acme:error:rateLimited","detail":"too many certificates (5) already issued for this exact set of identifiers","status":429}"#,
+        );
+        let config = Config::test(80, Path::new("/tmp"));
+        let report = improve_report(&config, report, None);
+        assert_eq!(
+            report.to_string(),
+            "Hit a rate limit of the certificate authority"
+        );
+        assert!(report
+            .chain()
+            .any(|e| e.to_string().contains("too many certificates")));
+    }
+
+    #[test]
+    fn subproblem_types_count() {
+        let report = problem(
+            r#"{"type":"urn:ietf:params:acme:error:malformed","subproblems":[{"type":"urn:ietf:params:acme:error:caa","detail":"CAA record prevents issuance"}]}"#,
+        );
+        let config = Config::test(80, Path::new("/tmp"));
+        let report = improve_report(&config, report, None);
+        assert!(report.to_string().starts_with("A CAA record forbids"));
+
+        let unrelated = eyre::eyre!("not a problem document");
+        let unrelated = improve_report(&config, unrelated, None);
+        assert_eq!(unrelated.to_string(), "not a problem document");
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        let retry_after = RetryAfter {
+            received: SystemTime::now(),
+            value: "3600".to_owned(),
+        };
+        assert!(resets_at(&retry_after).ends_with("(in 60 minutes)"));
+
+        let date = "Fri, 31 Dec 1999 23:59:59 GMT";
+        let retry_after = RetryAfter {
+            received: SystemTime::now(),
+            value: date.to_owned(),
+        };
+        assert_eq!(resets_at(&retry_after), date);
+    }
+}
@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use std::time::Duration;
 
 use color_eyre::eyre::Context;
@@ -5,17 +6,31 @@ use color_eyre::{eyre, Help};
 use reqwest::{Response, StatusCode};
 use tracing::{debug, instrument};
 
-use crate::config::Config;
-use crate::renew::server::Http01Challenge;
+use crate::renew::server::{Http01Challenge, SELF_CHECK_USER_AGENT};
 
 const APP: &str = env!("CARGO_PKG_NAME");
+/// Of an unexpected response, enough to recognize a web page
+const MAX_SHOWN_BODY: usize = 200;
 
 async fn check_response(resp: Response, key_auth: &str, domain: &str) -> eyre::Result<()> {
     match resp.status() {
         StatusCode::OK => {
-            let body_bytes = resp.bytes().await.unwrap();
-            assert_eq!(body_bytes, key_auth.as_bytes());
-            Ok(())
+            let body = resp
+                .bytes()
+                .await
+                .wrap_err_with(|| format!("Could not read the response via {domain}"))?;
+            if body == key_auth.as_bytes() {
+                return Ok(());
+            }
+            let body = String::from_utf8_lossy(&body);
+            let mut received: String = body.chars().take(MAX_SHOWN_BODY).collect();
+            if received.len() < body.len() {
+                received.push_str("...");
+            }
+            Err(eyre::eyre!("Got the wrong challenge response via {domain}"))
+                .with_note(|| format!("expected: {key_auth}"))
+                .with_note(|| format!("received: {received}"))
+                .suggestion("Another server or a different directory is serving the challenge path, for example a single page app answering every path")
         }
         StatusCode::SERVICE_UNAVAILABLE | StatusCode::NOT_FOUND => {
             Err(eyre::eyre!(
@@ -34,10 +49,17 @@ async fn check_response(resp: Response, key_auth: &str, domain: &str) -> eyre::R
 
 #[instrument(ret, skip(key_auth, path))]
 async fn check(path: &str, domain: &str, key_auth: &str) -> eyre::Result<()> {
-    let url = format!("http://{domain}{path}");
+    let url = match domain.parse() {
+        Ok(IpAddr::V6(ip)) => format!("http://[{ip}]{path}"),
+        _ => format!("http://{domain}{path}"),
+    };
     debug!("checking: {url}");
     let client = reqwest::Client::new();
-    let get = client.get(&url).timeout(Duration::from_millis(250)).send();
+    let get = client
+        .get(&url)
+        .header(reqwest::header::USER_AGENT, SELF_CHECK_USER_AGENT)
+        .timeout(Duration::from_millis(250))
+        .send();
     match get.await {
         Ok(resp) => check_response(resp, key_auth, domain).await,
         Err(e) if e.is_timeout() || e.is_connect() => {
@@ -49,17 +71,17 @@ async fn check(path: &str, domain: &str, key_auth: &str) -> eyre::Result<()> {
     }
 }
 
-pub async fn server(config: &Config, challanges: &[Http01Challenge]) -> eyre::Result<()> {
-    let Http01Challenge {
-        token, key_auth, ..
-    } = challanges
-        .first()
-        .expect("there is always one domain thus one challange");
-
-    let path = format!("/.well-known/acme-challenge/{token}");
+pub async fn server(challanges: &[Http01Challenge]) -> eyre::Result<()> {
     // TODO: make this run concurrently <02-06-23>
-    for domain in &config.domains {
-        check(&path, domain, key_auth).await?;
+    for Http01Challenge {
+        token,
+        key_auth,
+        id,
+        ..
+    } in challanges
+    {
+        let path = format!("/.well-known/acme-challenge/{token}");
+        check(&path, id, key_auth).await?;
     }
 
     Ok(())
@@ -79,3 +101,26 @@ impl<T> WithLocalIp for eyre::Result<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wrong_body_is_an_error() {
+        let page = "<html>single page app</html>";
+        let resp = Response::from(http::Response::new(page));
+        let err = check_response(resp, "token.key", "example.org")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("wrong challenge response"),
+            "{err:?}"
+        );
+
+        let resp = Response::from(http::Response::new("token.key"));
+        check_response(resp, "token.key", "example.org")
+            .await
+            .unwrap();
+    }
+}
@@ -1,13 +1,31 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
 use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
 use renewc::name;
 use service_install::schedule::Schedule;
 use service_install::{install_system, tui};
 
-use renewc::config::InstallArgs;
+use renewc::config::{DnsProviderKind, InstallArgs};
+
+use crate::systemd;
+
+/// Marks unit files we created so uninstall can find them
+const UNIT_HEADER: &str = concat!("# Created by ", env!("CARGO_PKG_NAME"));
+const UNIT_DIR: &str = "/etc/systemd/system";
+/// Drop-in for the service that adds the socket
+const SOCKET_DROPIN: &str = "socket-activation.conf";
 
 fn format_args(args: InstallArgs) -> Vec<String> {
     let mut res = vec!["run".to_string()];
     let args = args.run;
+    let format = |p: &std::path::Path| {
+        p.to_str()
+            .expect("only utf8 is supported for arguments")
+            .to_string()
+    };
 
     for domain in args.domain {
         res.push("--domain".to_string());
@@ -24,6 +42,45 @@ fn format_args(args: InstallArgs) -> Vec<String> {
     }
 
     res.extend(["--port".to_string(), args.port.to_string()]);
+    for addr in args.bind {
+        res.extend(["--bind".to_string(), addr.to_string()]);
+    }
+    if args.tls_alpn {
+        res.push("--tls-alpn".to_string());
+        res.extend(["--tls-port".to_string(), args.tls_port.to_string()]);
+    }
+    if let Some(webroot) = &args.webroot {
+        res.extend(["--webroot".to_string(), format(webroot)]);
+    }
+    if let Some(upstream) = &args.upstream {
+        res.extend(["--upstream".to_string(), upstream.to_string()]);
+    }
+    if args.redirect_https {
+        res.push("--redirect-https".to_string());
+    }
+    if let Some(csr) = &args.csr {
+        // conflicts with the key options, the key is not ours to make
+        res.extend(["--csr".to_string(), format(csr)]);
+    } else {
+        res.extend(["--key-type".to_string(), args.key_type.to_string()]);
+        if args.reuse_key {
+            res.push("--reuse-key".to_string());
+        }
+    }
+    if let Some(issuer) = &args.preferred_chain {
+        res.extend(["--preferred-chain".to_string(), issuer.clone()]);
+    }
+    if let Some(profile) = &args.profile {
+        res.extend(["--profile".to_string(), profile.clone()]);
+    }
+    res.extend([
+        "--validation-timeout".to_string(),
+        args.validation_timeout.to_string(),
+    ]);
+    res.extend([
+        "--finalize-timeout".to_string(),
+        args.finalize_timeout.to_string(),
+    ]);
     if let Some(reload) = args.reload {
         res.extend(["--reload".to_string(), reload]);
     }
@@ -39,14 +96,57 @@ fn format_args(args: InstallArgs) -> Vec<String> {
     if args.debug {
         res.push("--debug".to_string());
     }
+
+    let ca = args.ca;
+    if let Some(directory) = ca.acme_directory {
+        res.extend(["--acme-directory".to_string(), directory]);
+    }
+    if let Some(directory) = ca.acme_staging_directory {
+        res.extend(["--acme-staging-directory".to_string(), directory]);
+    }
+    if let Some(root) = ca.acme_root {
+        res.extend(["--acme-root".to_string(), format(&root)]);
+    }
+    if let Some(dir) = ca.account_dir {
+        res.extend(["--account-dir".to_string(), format(&dir)]);
+    }
+    // the hmac key itself is a secret and must never end up in the unit,
+    // only a path to it may. `check_eab` refuses to install without one.
+    if let Some((kid, path)) = ca.eab_kid.zip(ca.eab_hmac_key_file) {
+        res.extend(["--eab-kid".to_string(), kid]);
+        res.extend(["--eab-hmac-key-file".to_string(), format(&path)]);
+    }
+
+    let dns = args.dns;
+    if let Some(provider) = dns.dns_provider {
+        res.extend(["--dns-provider".to_string(), provider.to_string()]);
+    }
+    if let Some(server) = dns.rfc2136_server {
+        res.extend(["--rfc2136-server".to_string(), server]);
+    }
+    if let Some(zone) = dns.rfc2136_zone {
+        res.extend(["--rfc2136-zone".to_string(), zone]);
+    }
+    if let Some(key_name) = dns.rfc2136_key_name {
+        res.extend(["--rfc2136-key-name".to_string(), key_name]);
+        res.extend([
+            "--rfc2136-key-algorithm".to_string(),
+            dns.rfc2136_key_algorithm.to_string(),
+        ]);
+    }
+    if let Some(key_file) = dns.rfc2136_key_file {
+        res.extend(["--rfc2136-key-file".to_string(), format(&key_file)]);
+    }
+    if let Some(hook) = dns.dns_present_hook {
+        res.extend(["--dns-present-hook".to_string(), hook]);
+    }
+    if let Some(hook) = dns.dns_cleanup_hook {
+        res.extend(["--dns-cleanup-hook".to_string(), hook]);
+    }
+
     let args = args.output_config;
     res.extend(["--output".to_string(), args.output.to_string()]);
 
-    let format = |p: &std::path::Path| {
-        p.to_str()
-            .expect("only utf8 is supported for arguments")
-            .to_string()
-    };
     res.extend([
         "--certificate-path".to_string(),
         format(&args.certificate_path),
@@ -61,23 +161,204 @@ fn format_args(args: InstallArgs) -> Vec<String> {
     res
 }
 
+/// The hmac key itself never ends up in the unit. Without the key file the
+/// service would silently run without external account binding.
+fn check_eab(args: &InstallArgs) -> eyre::Result<()> {
+    let ca = &args.run.ca;
+    if ca.eab_kid.is_some() && ca.eab_hmac_key_file.is_none() {
+        return Err(eyre::eyre!(
+            "The service can not use external account binding without a key file"
+        ))
+        .suggestion(
+            "Pass the HMAC key using `--eab-hmac-key-file` or, if the account \
+            already exists, leave out `--eab-kid`",
+        );
+    }
+    Ok(())
+}
+
+/// The manual provider waits for someone to create the records, nobody is
+/// there when the service runs.
+fn check_dns_provider(args: &InstallArgs) -> eyre::Result<()> {
+    if args.run.dns.dns_provider == Some(DnsProviderKind::Manual) {
+        return Err(eyre::eyre!(
+            "The service can not use the manual dns provider, it needs someone to create the records"
+        ))
+        .suggestion(
+            "Use `--dns-provider exec` with hooks that create the records, or \
+            `--dns-provider rfc2136` if your DNS server supports dynamic updates",
+        );
+    }
+    Ok(())
+}
+
 pub fn perform(args: InstallArgs) -> eyre::Result<()> {
+    check_eab(&args)?;
+    check_dns_provider(&args)?;
     let schedule = Schedule::Daily(args.time.0);
+    let service_name = service_name(&args)?;
+    let socket = args
+        .socket_activation
+        .then(|| socket_unit(&args, &service_name));
+    let run_as = args.run_as.clone();
 
-    let steps = install_system!()
+    let spec = install_system!()
         .current_exe()
         .wrap_err("Could not get path to current exe")?
         .overwrite_existing(true)
-        .service_name(service_name(&args)?)
+        .service_name(service_name.clone())
         .on_schedule(schedule)
-        .args(format_args(args))
+        .args(format_args(args));
+    let spec = match run_as {
+        Some(user) => spec.run_as(user),
+        None => spec,
+    };
+    let steps = spec
         .prepare_install()
         .wrap_err("Could not prepare installation")?;
     tui::install::start(steps, true).wrap_err("Installation failed")?;
 
+    match socket {
+        Some(unit) => {
+            install_socket(&service_name, &unit).wrap_err("Could not set up socket activation")?;
+        }
+        // a previous install of this service may have used it
+        None if dropin_path(&service_name).exists() => {
+            remove_socket(&service_name).wrap_err("Could not remove socket activation")?;
+        }
+        None => (),
+    }
+
     Ok(())
 }
 
+fn socket_name(service_name: &str) -> String {
+    format!("{service_name}-challenge.socket")
+}
+
+fn socket_path(service_name: &str) -> PathBuf {
+    Path::new(UNIT_DIR).join(socket_name(service_name))
+}
+
+fn dropin_path(service_name: &str) -> PathBuf {
+    Path::new(UNIT_DIR)
+        .join(format!("{service_name}.service.d"))
+        .join(SOCKET_DROPIN)
+}
+
+/// The socket is bound to the service so it only listens while renewc runs.
+/// If it listened all the time any connection would start the service.
+fn socket_unit(args: &InstallArgs, service_name: &str) -> String {
+    let args = &args.run;
+    let mut ports = vec![args.port];
+    if args.tls_alpn {
+        ports.push(args.tls_port);
+    }
+
+    let mut unit = format!(
+        "{UNIT_HEADER}\n\
+        [Unit]\n\
+        Description=Challenge port(s) for {service_name}\n\
+        BindsTo={service_name}.service\n\
+        \n\
+        [Socket]\n\
+        Service={service_name}.service\n"
+    );
+    for port in ports {
+        if args.bind.is_empty() {
+            writeln!(unit, "ListenStream={port}").expect("writing to string never fails");
+        }
+        for addr in &args.bind {
+            let addr = SocketAddr::new(*addr, port);
+            writeln!(unit, "ListenStream={addr}").expect("writing to string never fails");
+        }
+    }
+    unit.push_str("BindIPv6Only=both\n");
+    unit
+}
+
+/// Makes the (timer started) service start the socket and inherit it
+fn socket_dropin(service_name: &str) -> String {
+    let socket = socket_name(service_name);
+    format!(
+        "{UNIT_HEADER}\n\
+        [Unit]\n\
+        Requires={socket}\n\
+        After={socket}\n\
+        \n\
+        [Service]\n\
+        Sockets={socket}\n"
+    )
+}
+
+fn write_unit(path: &Path, content: &str) -> eyre::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .wrap_err("Could not create directory")
+            .with_note(|| format!("path: {}", dir.display()))?;
+    }
+    std::fs::write(path, content)
+        .wrap_err("Could not write unit file")
+        .with_note(|| format!("path: {}", path.display()))
+}
+
+fn install_socket(service_name: &str, unit: &str) -> eyre::Result<()> {
+    let path = socket_path(service_name);
+    write_unit(&path, unit)?;
+    let dropin = dropin_path(service_name);
+    write_unit(&dropin, &socket_dropin(service_name))?;
+    systemd::daemon_reload().wrap_err("Could not load the socket unit")?;
+    println!("Installed {} and {}", path.display(), dropin.display());
+    Ok(())
+}
+
+fn remove_socket(service_name: &str) -> eyre::Result<()> {
+    systemd::systemctl(&["stop"], &socket_name(service_name))
+        .wrap_err("Could not stop socket unit")?;
+    for path in [socket_path(service_name), dropin_path(service_name)] {
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!("Removed {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not remove unit file")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        }
+    }
+    if let Some(dir) = dropin_path(service_name).parent() {
+        // only succeeds if nothing else is in there
+        let _ = std::fs::remove_dir(dir);
+    }
+    systemd::daemon_reload()
+}
+
+/// Services that have our socket activation drop-in while the service
+/// itself has been removed
+fn orphaned_sockets() -> eyre::Result<Vec<String>> {
+    let mut res = Vec::new();
+    let entries = std::fs::read_dir(UNIT_DIR).wrap_err("Could not list systemd units")?;
+    for entry in entries {
+        let dir = entry.wrap_err("Could not list systemd units")?.path();
+        let Some(service) = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".service.d"))
+        else {
+            continue;
+        };
+        let has_dropin = std::fs::read_to_string(dir.join(SOCKET_DROPIN))
+            .is_ok_and(|dropin| dropin.starts_with(UNIT_HEADER));
+        let service_exists = Path::new(UNIT_DIR)
+            .join(format!("{service}.service"))
+            .exists();
+        if has_dropin && !service_exists {
+            res.push(service.to_owned());
+        }
+    }
+    Ok(res)
+}
+
 fn service_name(args: &InstallArgs) -> eyre::Result<String> {
     Ok(if let Some(service_name) = &args.service_name {
         service_name.to_owned()
@@ -99,6 +380,103 @@ pub fn undo() -> eyre::Result<()> {
         .prepare_remove()
         .wrap_err("Could not prepare for removal")?;
     tui::removal::start(steps).wrap_err("Removal failed")?;
+    // only for the services that were just removed
+    for service in orphaned_sockets()? {
+        remove_socket(&service)
+            .wrap_err("Could not remove socket activation")
+            .with_note(|| format!("service: {service}"))?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn eab_needs_key_file() {
+        let args = |extra: &[&str]| {
+            let base = [
+                "install",
+                "--domain",
+                "example.org",
+                "--certificate-path",
+                "example.pem",
+                "--eab-kid",
+                "kid",
+            ];
+            InstallArgs::parse_from(base.iter().chain(extra))
+        };
+        assert!(check_eab(&args(&["--eab-hmac-key", "secret"])).is_err());
+        assert!(check_eab(&args(&["--eab-hmac-key-file", "/etc/renewc/hmac"])).is_ok());
+    }
+
+    #[test]
+    fn refuses_manual_dns_provider() {
+        let args = |provider: &str| {
+            InstallArgs::parse_from([
+                "install",
+                "--domain",
+                "example.org",
+                "--certificate-path",
+                "example.pem",
+                "--dns-provider",
+                provider,
+                "--dns-present-hook",
+                "/etc/renewc/present",
+                "--dns-cleanup-hook",
+                "/etc/renewc/cleanup",
+            ])
+        };
+        assert!(check_dns_provider(&args("manual")).is_err());
+        assert!(check_dns_provider(&args("exec")).is_ok());
+    }
+
+    #[test]
+    fn socket_unit_listens_on_challenge_ports() {
+        let args = InstallArgs::parse_from([
+            "install",
+            "--domain",
+            "example.org",
+            "--certificate-path",
+            "example.pem",
+            "--tls-alpn",
+            "--bind",
+            "::1",
+            "--bind",
+            "127.0.0.1",
+        ]);
+        let unit = socket_unit(&args, "renewc_example.org");
+        assert!(unit.starts_with(UNIT_HEADER));
+        assert!(unit.contains("BindsTo=renewc_example.org.service"));
+        assert!(!unit.contains("[Install]"), "must never listen by itself");
+        let dropin = socket_dropin("renewc_example.org");
+        assert!(dropin.contains("Sockets=renewc_example.org-challenge.socket"));
+        let listen: Vec<_> = unit
+            .lines()
+            .filter_map(|l| l.strip_prefix("ListenStream="))
+            .collect();
+        assert_eq!(
+            listen,
+            ["[::1]:80", "127.0.0.1:80", "[::1]:443", "127.0.0.1:443"]
+        );
+    }
+
+    #[test]
+    fn unit_with_csr_parses() {
+        let args = InstallArgs::parse_from([
+            "install",
+            "--domain",
+            "example.org",
+            "--certificate-path",
+            "example.pem",
+            "--csr",
+            "example.csr",
+        ]);
+        let args = format_args(args);
+        assert!(!args.iter().any(|arg| arg == "--key-type"), "{args:?}");
+        crate::Cli::try_parse_from(std::iter::once("renewc".to_string()).chain(args)).unwrap();
+    }
+}
@@ -4,7 +4,7 @@
 use std::io::Write;
 
 use cert::format::PemItem;
-use cert::info::Info as CertInfo;
+use cert::info::{Info as CertInfo, RenewalWindow};
 use cert::Signed;
 use color_eyre::eyre;
 
@@ -24,11 +24,19 @@ use owo_colors::OwoColorize;
 pub trait ACME {
     #[allow(async_fn_in_trait)]
     async fn renew<P: PemItem, W: Write + Send>(
-        &self,
+        &mut self,
         config: &Config,
         stdout: &mut W,
         debug: bool,
     ) -> eyre::Result<Signed<P>>;
+
+    /// when the certificate authority suggests renewing the certificate
+    #[allow(async_fn_in_trait)]
+    async fn renewal_window(
+        &self,
+        config: &Config,
+        info: &CertInfo,
+    ) -> eyre::Result<Option<RenewalWindow>>;
 }
 
 pub async fn run<P: PemItem>(
@@ -42,10 +50,23 @@ pub async fn run<P: PemItem>(
         return Ok(Some(signed));
     }
 
-    match CertInfo::from_disk(config, out) {
-        Ok(Some(cert_info)) => {
-            info!(out, "Existing certificate: {}", cert_filename(config));
-            match advise::given_existing(config, cert_info, out) {
+    let mut config = config.clone();
+    match CertInfo::from_disk(&config, out) {
+        Ok(Some(mut cert_info)) => {
+            info!(out, "Existing certificate: {}", cert_filename(&config));
+            // the certificate authority can only know certificates it issued
+            if cert_info.staging != config.production {
+                config.replaces.clone_from(&cert_info.ari_cert_id);
+            }
+            // without it we fall back to renewing shortly before expiry
+            match acme_impl.renewal_window(&config, &cert_info).await {
+                Ok(window) => cert_info.renewal_window = window,
+                Err(e) => warn!(
+                    out,
+                    "Could not get renewal information from the certificate authority: {e:#}"
+                ),
+            }
+            match advise::given_existing(&config, cert_info, out) {
                 CheckResult::Refuse { status, warning } => {
                     if let Some(status) = status {
                         info!(out, "{status}");
@@ -62,13 +83,20 @@ pub async fn run<P: PemItem>(
     }
 
     if config.production {
-        check_against_staging(out, config, acme_impl, debug).await?;
+        if config.ca.has_staging() {
+            check_against_staging(out, &config, acme_impl, debug).await?;
+        } else {
+            info!(
+                out,
+                "certificate authority has no staging environment, can not check if request can succeed"
+            );
+        }
         info!(out, "requesting production certificate");
     } else {
         info!(out, "requesting staging certificate");
     }
     let mut stdout = IndentedOut::new(out);
-    let signed = acme_impl.renew(config, &mut stdout, debug).await?;
+    let signed = acme_impl.renew(&config, &mut stdout, debug).await?;
     Ok(Some(signed))
 }
 
@@ -81,12 +109,12 @@ async fn check_against_staging(
     info!(out, "checking if request can succeed using staging");
     let staging_config = Config {
         production: false,
+        replaces: None,
         ..config.clone()
     };
-    Ok({
-        let mut stdout = IndentedOut::new(out);
-        let _: Signed<pem::Pem> = acme_impl.renew(&staging_config, &mut stdout, debug).await?;
-    })
+    let mut stdout = IndentedOut::new(out);
+    let _: Signed<pem::Pem> = acme_impl.renew(&staging_config, &mut stdout, debug).await?;
+    Ok(())
 }
 
 fn print_advice_error_chain(stdout: &mut (impl Write + Send), e: eyre::Error) {
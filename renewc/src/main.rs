@@ -5,7 +5,7 @@ use color_eyre::eyre::{self, Context};
 use renewc::cert::Signed;
 use renewc::Config;
 
-use renewc::config::Commands;
+use renewc::config::{AccountAction, AccountConfig, Commands, RevokeConfig, RevokedFiles};
 use renewc::renew::InstantAcme;
 use renewc::{cert, run};
 
@@ -42,9 +42,13 @@ async fn main() -> eyre::Result<()> {
     match cli.command {
         Commands::Run(args) => {
             let config = Config::try_from(args)?;
-            let Some(certs): Option<Signed<pem::Pem>> =
-                run(&mut InstantAcme {}, &mut stdout, &config, debug).await?
-            else {
+            let mut acme = InstantAcme::default();
+            let certs: Option<Signed<pem::Pem>> =
+                run(&mut acme, &mut stdout, &config, debug).await?;
+            acme.shutdown()
+                .await
+                .wrap_err("Could not shut down challenge server")?;
+            let Some(certs) = certs else {
                 return Ok(());
             };
             cert::store::on_disk(&config, certs, &mut stdout)
@@ -58,20 +62,58 @@ async fn main() -> eyre::Result<()> {
         Commands::Install(args) => {
             let question = "Missing `--production` argument, certificates produced by \
                             service or job will not be valid";
-            if !args.run.production {
-                if exit_requested(&mut stdout, &question) {
-                    return Ok(());
-                }
+            if !args.run.production && exit_requested(&mut stdout, question) {
+                return Ok(());
             }
             install::perform(args)?;
         }
         Commands::Uninstall => {
             install::undo().wrap_err("failed to uninstall")?;
         }
+        Commands::Revoke(args) => {
+            let (reason, with_cert_key, files) = (args.reason, args.with_cert_key, args.files);
+            let config = RevokeConfig::try_from(args)?;
+            let question = if files == RevokedFiles::Delete {
+                "Revoking the certificate can not be undone, its files will be removed"
+            } else {
+                "Revoking the certificate can not be undone"
+            };
+            if exit_requested(&mut stdout, question) {
+                return Ok(());
+            }
+            renewc::renew::revoke::revoke(&config, reason, with_cert_key, &mut stdout)
+                .await
+                .wrap_err("Could not revoke certificate")?;
+            cert::store::revoked(&config.output_config, files, &mut stdout)?;
+        }
+        Commands::Account(action) => account(action, &mut stdout).await?,
     }
     Ok(())
 }
 
+async fn account(action: AccountAction, stdout: &mut impl Write) -> eyre::Result<()> {
+    use renewc::renew::account;
+
+    match action {
+        AccountAction::Show(args) => account::show(&AccountConfig::try_from(args)?, stdout).await,
+        AccountAction::UpdateContact {
+            email,
+            account: args,
+        } => account::update_contact(&AccountConfig::try_from(args)?, &email, stdout).await,
+        AccountAction::KeyRollover(args) => {
+            account::key_rollover(&AccountConfig::try_from(args)?, stdout).await
+        }
+        AccountAction::Deactivate(args) => {
+            let config = AccountConfig::try_from(args)?;
+            let question = "Deactivating the account can not be undone";
+            if exit_requested(stdout, question) {
+                return Ok(());
+            }
+            account::deactivate(&config, stdout).await
+        }
+    }
+}
+
 #[must_use]
 fn exit_requested(w: &mut impl std::io::Write, question: &str) -> bool {
     use std::io::IsTerminal;
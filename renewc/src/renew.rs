@@ -4,171 +4,318 @@ use std::time::Duration;
 
 use color_eyre::eyre::{self, Context};
 use color_eyre::Help;
-use rcgen::{Certificate, CertificateParams, DistinguishedName};
-use tokio::time::{sleep, sleep_until, Instant};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, KeyPair};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
 use tracing::{debug, error};
 
+use crate::cert;
 use crate::cert::format::PemItem;
+use crate::cert::info::{Info as CertInfo, RenewalWindow};
 use crate::cert::Signed;
-use crate::config::Config;
+use crate::config::{Config, KeyType};
 use crate::diagnostics;
 
+pub mod account;
+mod ari;
+mod authz;
+mod chain;
+pub(crate) mod client;
+pub mod dns;
+mod jws;
+pub mod revoke;
 pub mod server;
+pub mod tls_alpn;
+mod webroot;
 use acme::{
-    Account, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder,
-    Order, OrderState, OrderStatus,
+    Account, AuthorizationStatus, ChallengeType, Identifier, NewOrder, Order, OrderState,
+    OrderStatus,
 };
+use client::LastRetryAfter;
+use dns::{Dns01Challenge, TxtRecord};
 use instant_acme as acme;
-use server::Http01Challenge;
+use server::{Http01Challenge, Server};
+use tls_alpn::TlsAlpn01Challenge;
 
 use super::ACME;
 
-// Create a new account. This will generate a fresh ECDSA key for you.
-// Alternatively, restore an account from serialized credentials by
-// using `Account::from_credentials()`.
-#[tracing::instrument(skip_all)]
-async fn account(config: &Config) -> Result<Account, acme::Error> {
-    let url = if config.production {
-        LetsEncrypt::Production.url()
-    } else {
-        LetsEncrypt::Staging.url()
-    };
-    let contact: Vec<_> = config
-        .email
-        .iter()
-        .map(|addr| format!("mailto:{addr}"))
+fn check_profile(account: &Account, profile: &str) -> eyre::Result<()> {
+    let offered: Vec<_> = account
+        .profiles()
+        .map(|p| format!("{}: {}", p.name, p.description))
         .collect();
-
-    let (account, _account_credentials) = Account::create(
-        &NewAccount {
-            contact: contact
-                .iter()
-                .map(String::as_str)
-                .collect::<Vec<_>>()
-                .as_slice(),
-            terms_of_service_agreed: true,
-            only_return_existing: false,
-        },
-        url,
-        None,
-    )
-    .await?;
-    Ok(account)
+    if offered.is_empty() {
+        return Err(eyre::eyre!(
+            "The certificate authority does not support profiles"
+        ))
+        .suggestion("Request the certificate without `--profile`");
+    }
+    if account.profiles().any(|p| p.name == profile) {
+        return Ok(());
+    }
+    Err(eyre::eyre!(
+        "The certificate authority does not offer profile: {profile}"
+    ))
+    .with_note(|| format!("Offered profiles:\n\t{}", offered.join("\n\t")))
 }
 
 #[tracing::instrument(skip_all)]
-async fn order(account: &Account, names: &[String]) -> Result<Order, acme::Error> {
-    let identifiers = names
+async fn order(account: &Account, config: &Config) -> eyre::Result<Order> {
+    let identifiers = config
+        .domains
         .iter()
-        .map(|name| Identifier::Dns(name.into()))
-        .collect::<Vec<_>>();
-    let order = account
-        .new_order(&NewOrder {
-            identifiers: &identifiers,
+        .map(|name| match name.parse() {
+            Ok(ip) => Identifier::Ip(ip),
+            Err(_) => Identifier::Dns(name.into()),
         })
-        .await?;
+        .collect::<Vec<_>>();
+    if let Some(profile) = &config.profile {
+        check_profile(account, profile)?;
+    }
+    let new_order = || {
+        let new_order = NewOrder::new(&identifiers);
+        match &config.profile {
+            Some(profile) => new_order.profile(profile),
+            None => new_order,
+        }
+    };
+    if let Some(replaces) = &config.replaces {
+        match account
+            .new_order(&new_order().replaces(replaces.clone()))
+            .await
+        {
+            Ok(order) => return Ok(order),
+            // for example the certificate was already replaced, a new
+            // order without it is still fine
+            Err(e) => debug!("could not order a replacement for {replaces}: {e}"),
+        }
+    }
+    let order = account.new_order(&new_order()).await?;
 
     Ok(order)
 }
 
-// Pick the desired challenge type and prepare the response.
+pub struct Challenges {
+    pub http: Vec<Http01Challenge>,
+    pub dns: Vec<Dns01Challenge>,
+    pub tls_alpn: Vec<TlsAlpn01Challenge>,
+}
+
+impl Challenges {
+    fn urls(&self) -> Vec<&str> {
+        self.http
+            .iter()
+            .map(|c| c.url.as_str())
+            .chain(self.dns.iter().map(|c| c.url.as_str()))
+            .chain(self.tls_alpn.iter().map(|c| c.url.as_str()))
+            .collect()
+    }
+}
+
+// Pick the desired challenge type for each authorization and prepare
+// the response. Dns is preferred if a provider has been configured. Http is
+// only used if tls-alpn was not asked for.
 #[tracing::instrument(skip_all)]
-async fn prepare_challenge(order: &mut Order) -> eyre::Result<Vec<Http01Challenge>> {
-    let authorizations = order.authorizations().await.unwrap();
-    let mut challenges = Vec::with_capacity(authorizations.len());
-    for authz in authorizations {
+async fn prepare_challenges(config: &Config, order: &mut Order) -> eyre::Result<Challenges> {
+    let mut challenges = Challenges {
+        http: Vec::new(),
+        dns: Vec::new(),
+        tls_alpn: Vec::new(),
+    };
+    let mut authorizations = order.authorizations();
+    while let Some(authz) = authorizations.next().await {
+        let mut authz = authz.wrap_err("could not get authorization from server")?;
+        let id = authz.identifier().to_string();
         match authz.status {
             AuthorizationStatus::Pending => {}
             AuthorizationStatus::Valid => continue,
-            _ => unreachable!("got unexpected status, authorization: {authz:?}"),
+            // invalid, expired, revoked or deactivated
+            status => {
+                let mut report = Err(eyre::eyre!("Authorization for {id} is {status:?}"))
+                    .suggestion("Try again, the next order gets new authorizations");
+                for challenge in &authz.challenges {
+                    if let Some(problem) = &challenge.error {
+                        report = report.with_note(|| format!("{:?}: {problem}", challenge.r#type));
+                    }
+                }
+                return report;
+            }
         }
 
-        // We'll use the DNS challenges for this example, but you could
-        // pick something else to use here.
-        let challenge = authz
+        // ip identifiers can not be validated using dns (RFC 8738)
+        let domain = match authz.identifier().identifier.clone() {
+            Identifier::Dns(domain) => Some(domain),
+            Identifier::Ip(_) => None,
+            _ => unreachable!("we only request dns and ip identifiers"),
+        };
+        let offered: Vec<_> = authz.challenges.iter().map(|c| c.r#type.clone()).collect();
+        let ty = pick_challenge(config, &id, domain.is_some(), &offered)?;
+        let challenge = authz.challenge(ty.clone()).expect("picked from offered");
+        match ty {
+            ChallengeType::Dns01 => {
+                let domain = domain.expect("dns-01 is only picked for domains");
+                let value = challenge.key_authorization().dns_value();
+                challenges.dns.push(Dns01Challenge {
+                    url: challenge.url.clone(),
+                    record: TxtRecord::new(&domain, value),
+                    id,
+                });
+            }
+            ChallengeType::TlsAlpn01 => challenges.tls_alpn.push(TlsAlpn01Challenge {
+                url: challenge.url.clone(),
+                digest: challenge.key_authorization().digest().as_ref().to_vec(),
+                id,
+            }),
+            _ => challenges.http.push(Http01Challenge {
+                url: challenge.url.clone(),
+                token: challenge.token.clone(),
+                key_auth: challenge.key_authorization().as_str().to_owned(),
+                id,
+            }),
+        }
+    }
+    Ok(challenges)
+}
+
+/// Which challenge to use for one authorization. Dns is preferred when it is
+/// configured, ip identifiers (`is_domain` false) can not use it (RFC 8738).
+fn pick_challenge(
+    config: &Config,
+    id: &str,
+    is_domain: bool,
+    offered: &[ChallengeType],
+) -> eyre::Result<ChallengeType> {
+    let dns_possible = is_domain && offered.contains(&ChallengeType::Dns01);
+    if config.dns.is_some() && dns_possible {
+        return Ok(ChallengeType::Dns01);
+    }
+    let wanted = if config.tls_alpn {
+        ChallengeType::TlsAlpn01
+    } else {
+        ChallengeType::Http01
+    };
+    if offered.contains(&wanted) {
+        return Ok(wanted);
+    }
+
+    let report = Err(eyre::eyre!("No supported challenge offered for: {id}"))
+        .with_note(|| format!("offered challenges: {offered:?}"));
+    if dns_possible {
+        report.suggestion("Use a dns challenge by setting `--dns-provider`")
+    } else if config.tls_alpn && offered.contains(&ChallengeType::Http01) {
+        report.suggestion("Use a http challenge by leaving out `--tls-alpn`")
+    } else {
+        report
+    }
+}
+
+// Let the server know we're ready to accept the challenges.
+#[tracing::instrument(skip_all)]
+async fn set_challenges_ready(order: &mut Order, urls: &[&str]) -> eyre::Result<()> {
+    let mut authorizations = order.authorizations();
+    while let Some(authz) = authorizations.next().await {
+        let mut authz = authz.wrap_err("could not get authorization from server")?;
+        let Some(ty) = authz
             .challenges
             .iter()
-            .find(|c| c.r#type == ChallengeType::Http01)
-            .ok_or_else(|| eyre::eyre!("no http01 challenge found"))?;
-
-        let Identifier::Dns(identifier) = authz.identifier;
-        let challenge = Http01Challenge {
-            url: challenge.url.clone(),
-            token: challenge.token.clone(),
-            key_auth: order.key_authorization(challenge).as_str().to_owned(),
-            id: identifier.clone(),
+            .find(|c| urls.contains(&c.url.as_str()))
+            .map(|c| c.r#type.clone())
+        else {
+            continue;
         };
-        challenges.push(challenge);
+        let mut challenge = authz
+            .challenge(ty)
+            .expect("challenge type was just found in authorization");
+        challenge
+            .set_ready()
+            .await
+            .wrap_err("could not tell the server the challenge is ready")?;
     }
-    Ok(challenges)
+    Ok(())
 }
 
-trait TimeLeft {
-    fn duration_until(&self) -> Duration;
+// Exponential backoff starting at 250ms. Instant-acme waits longer if the
+// certificate authority asks for that using Retry-After.
+fn retry_policy(timeout: Duration) -> acme::RetryPolicy {
+    acme::RetryPolicy::new()
+        .initial_delay(Duration::from_millis(250))
+        .backoff(2.0)
+        .timeout(timeout)
 }
 
-impl TimeLeft for Instant {
-    fn duration_until(&self) -> Duration {
-        Instant::now().saturating_duration_since(*self)
+// Turn an error from polling the order into a report that includes the
+// last state of the order.
+fn polling_failed(
+    err: acme::Error,
+    state: &OrderState,
+    waiting_for: &str,
+    timeout_arg: &str,
+) -> eyre::Report {
+    let status = state.status;
+    let problem = state.error.as_ref().map(ToString::to_string);
+    let report = match err {
+        acme::Error::Timeout(retry_at) => {
+            let report = eyre::eyre!("Timed out waiting for {waiting_for}").suggestion(format!(
+                "The certificate authority might be having problems, try again later \
+                or increase `{timeout_arg}`"
+            ));
+            match retry_at {
+                Some(at) => report.with_note(|| {
+                    let wait = at.saturating_duration_since(std::time::Instant::now());
+                    format!(
+                        "The certificate authority asked to check again in {} seconds",
+                        wait.as_secs()
+                    )
+                }),
+                None => report,
+            }
+        }
+        other => eyre::Report::new(other).wrap_err(format!(
+            "Ran into a problem while waiting for {waiting_for}"
+        )),
+    };
+    let report = report.with_note(|| format!("last order status: {status:?}"));
+    match problem {
+        Some(problem) => report.with_note(|| format!("last order error: {problem}")),
+        None => report,
     }
 }
 
-// Exponentially back off until the order becomes ready or invalid.
+// Back off until the order becomes ready or invalid.
 #[tracing::instrument(skip_all)]
 async fn wait_for_order_rdy<'a>(
+    config: &Config,
     order: &'a mut Order,
-    challenges: &[Http01Challenge],
+    challenges: &Challenges,
     stdout: &mut impl Write,
     debug: bool,
 ) -> eyre::Result<&'a OrderState> {
-    // Let the server know we're ready to accept the challenges.
-    for Http01Challenge { url, .. } in challenges {
-        order.set_challenge_ready(url).await.unwrap();
-    }
-
-    const MAX_DURATION: Duration = Duration::from_secs(10);
-    let mut next_attempt = Instant::now();
-    let deadline = Instant::now() + MAX_DURATION;
-    let mut print_info = Some(Instant::now() + Duration::from_secs(2));
-    let mut delay = Duration::from_millis(250);
-    let mut attempt = 0;
-    let state = loop {
-        order
-            .refresh()
-            .await
-            .wrap_err("could not get update on order from server")?;
-
-        let status = match &order.state().status {
-            OrderStatus::Ready => break Ok(order.state()),
-            OrderStatus::Invalid => break Err(eyre::eyre!("order is invalid"))
-                .suggestion("sometimes this happens when the challenge server is not reachable. Try the debug flag to investigate"),
-            other => other,
-        };
-
-        if Instant::now() > deadline {
-            break Err(eyre::eyre!("order is not ready in time"))
-                .with_note(|| format!("last order status: {status:?}"));
+    set_challenges_ready(order, &challenges.urls()).await?;
+
+    const PRINT_INFO_AFTER: Duration = Duration::from_secs(2);
+    let policy = retry_policy(config.validation_timeout);
+    let polled = {
+        let poll = order.poll_ready(&policy);
+        tokio::pin!(poll);
+        tokio::select! {
+            res = &mut poll => res,
+            () = sleep(PRINT_INFO_AFTER) => {
+                writeln!(
+                    stdout,
+                    "certificate authority is taking longer then expected, waiting at most {} more seconds",
+                    config.validation_timeout.saturating_sub(PRINT_INFO_AFTER).as_secs()
+                )
+                .unwrap();
+                poll.await
+            }
         }
-        delay *= 2;
-        attempt += 1;
-        next_attempt = deadline.min(next_attempt + delay);
-
-        debug!(
-            attempt,
-            "order is not ready (status: {status:?}), waiting {delay:?} before retrying"
-        );
+    };
 
-        // None is smaller then all Some
-        if print_info.is_some_and(|p| next_attempt > p) {
-            print_info = None; // only print info once
-            writeln!(
-                stdout,
-                "certificate authority is taking longer then expected, waiting {} more seconds",
-                deadline.duration_until().as_secs()
-            )
-            .unwrap();
-        }
-        sleep_until(next_attempt).await;
+    let state = match polled {
+        Ok(OrderStatus::Ready) => Ok(order.state()),
+        Ok(_) => Err(eyre::eyre!("order is invalid"))
+            .suggestion("sometimes this happens when the challenge server is not reachable. Try the debug flag to investigate"),
+        Err(e) => Err(polling_failed(e, order.state(), "validation", "--validation-timeout")),
     };
 
     if debug && state.is_err() {
@@ -188,36 +335,151 @@ async fn wait_for_order_rdy<'a>(
     state
 }
 
+// Ring can not generate RSA keys, those are generated using the rsa crate
+// and then loaded by rcgen.
+#[tracing::instrument]
+pub(crate) fn generate_key(key_type: KeyType) -> eyre::Result<KeyPair> {
+    let alg = match key_type {
+        KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+        KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+        KeyType::Ed25519 => &rcgen::PKCS_ED25519,
+        KeyType::Rsa2048 | KeyType::Rsa3072 | KeyType::Rsa4096 => &rcgen::PKCS_RSA_SHA256,
+    };
+
+    let key_pair = if let Some(bits) = key_type.rsa_bits() {
+        use rsa::pkcs8::EncodePrivateKey;
+        let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), bits)
+            .wrap_err("Could not generate RSA key")?;
+        let der = key.to_pkcs8_der().wrap_err("Could not encode RSA key")?;
+        KeyPair::from_der_and_sign_algo(der.as_bytes(), alg)?
+    } else {
+        KeyPair::generate(alg)?
+    };
+    Ok(key_pair)
+}
+
+// Load the private key of the certificate currently on disk. Returns None
+// after warning the user if that is not possible.
+#[tracing::instrument(skip_all)]
+fn existing_key(config: &Config, stdout: &mut (impl Write + Send)) -> Option<KeyPair> {
+    let private_key = match cert::load::from_disk::<pem::Pem>(config, stdout) {
+        Ok(Some(cert::Signed {
+            private_key: Some(key),
+            ..
+        })) => key,
+        Ok(Some(_)) => {
+            crate::warn!(
+                stdout,
+                "Can not reuse private key: no private key found next to the existing certificate, generating a new key"
+            );
+            return None;
+        }
+        Ok(None) => {
+            crate::warn!(
+                stdout,
+                "Can not reuse private key: no existing certificate found, generating a new key"
+            );
+            return None;
+        }
+        Err(e) => {
+            crate::warn!(
+                stdout,
+                "Can not reuse private key: {e:#}, generating a new key"
+            );
+            return None;
+        }
+    };
+
+    let pem = String::from_utf8(private_key.as_bytes()).expect("pem encoding is always valid utf8");
+    match KeyPair::from_pem(&pem) {
+        Ok(key_pair) => Some(key_pair),
+        Err(e) => {
+            crate::warn!(
+                stdout,
+                "Can not reuse private key, it could not be loaded ({e}). Only PKCS#8 keys are supported. Generating a new key"
+            );
+            None
+        }
+    }
+}
+
 // If the order is ready, we can provision the certificate.
 // Use the rcgen library to create a Certificate Signing Request.
 #[tracing::instrument(skip_all)]
-fn prepare_sign_request(names: &[String]) -> Result<(Certificate, Vec<u8>), rcgen::Error> {
+fn prepare_sign_request(
+    names: &[String],
+    key_pair: KeyPair,
+) -> eyre::Result<(Certificate, Vec<u8>)> {
     let mut params = CertificateParams::new(names);
     params.distinguished_name = DistinguishedName::new();
-    let cert = Certificate::from_params(params).unwrap();
+    params.alg = key_pair.algorithm();
+    params.key_pair = Some(key_pair);
+    let cert = Certificate::from_params(params)?;
     let csr = cert.serialize_request_der()?;
     Ok((cert, csr))
 }
 
+// The http challenge server is kept running after validation so the
+// next order can use it too.
+async fn running_server<'a>(
+    config: &Config,
+    http_server: &'a mut Option<Server>,
+) -> eyre::Result<&'a mut Server> {
+    if http_server.as_ref().is_some_and(|s| !s.listens_as(config)) {
+        let previous = http_server.take().expect("just checked it is some");
+        previous.shutdown().await?;
+    }
+    match http_server {
+        Some(server) => Ok(server),
+        None => Ok(http_server.insert(Server::start(config).await?)),
+    }
+}
+
+async fn stopped(server: Option<&mut Server>) -> eyre::Report {
+    match server {
+        Some(server) => server.stopped().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Serve or publish the challenges then wait till the certificate authority
+// has validated them.
 #[tracing::instrument(skip_all)]
-pub async fn renew<P: PemItem>(
+async fn validate(
     config: &Config,
-    stdout: &mut impl Write,
+    order: &mut Order,
+    challenges: &Challenges,
+    http_server: &mut Option<Server>,
+    stdout: &mut (impl Write + Send),
     debug: bool,
-) -> eyre::Result<Signed<P>> {
-    let account = account(config).await?;
-    let mut order = order(&account, &config.domains)
-        .await
-        .wrap_err("Certificate authority can not issue a certificate")
-        .with_note(|| format!("names: {:?}", config.domains))?;
-
-    let challenges = prepare_challenge(&mut order).await?;
+) -> eyre::Result<()> {
+    // dropping the set stops the servers
+    let mut servers = JoinSet::new();
+    let mut serving = None;
+    if !challenges.http.is_empty() {
+        if let Some(webroot) = &config.webroot {
+            diagnostics::reachable::server(&challenges.http)
+                .await
+                .wrap_err("Challenge files in the webroot are not reachable via the domain")
+                .with_note(|| format!("webroot: {webroot:?}"))
+                .suggestion("Check if your web server serves the webroot directory on port 80")?;
+        } else {
+            let server = running_server(config, http_server).await?;
+            server.register(&challenges.http);
+            let reachable = diagnostics::reachable::server(&challenges.http)
+                .await
+                .wrap_err("Domain does not route to this application");
+            if reachable.is_err() {
+                server.deregister(&challenges.http);
+            }
+            reachable?;
+            serving = Some(server);
+        }
+    }
+    if !challenges.tls_alpn.is_empty() {
+        servers.spawn(tls_alpn::run(config, &challenges.tls_alpn).await?);
+    }
 
-    let server = server::run(config, &challenges).await?;
-    let mut server = tokio::spawn(server);
-    diagnostics::reachable::server(config, &challenges)
-        .await
-        .wrap_err("Domain does not route to this application")?;
     write!(
         stdout,
         "waiting: certificate authority is verifing we own the domain"
@@ -225,20 +487,93 @@ pub async fn renew<P: PemItem>(
     .unwrap();
     stdout.flush().unwrap();
 
-    let ready = wait_for_order_rdy(&mut order, &challenges, stdout, debug);
-    let state = tokio::select!(
-        res = ready => res?,
-        e = (&mut server) => {
+    let progress = serving.as_ref().map(|server| {
+        let all_served = server.all_served(&challenges.http);
+        tokio::spawn(async move {
+            all_served.await;
+            debug!("certificate authority requested all http challenges, waiting for it to validate them");
+        })
+    });
+    let ready = wait_for_order_rdy(config, order, challenges, stdout, debug);
+    let res = tokio::select!(
+        res = ready => res,
+        report = stopped(serving.as_deref_mut()) => Err(report),
+        Some(e) = servers.join_next() => {
             e.expect("server should never panic").wrap_err("Challenge server ran into problem")?;
             unreachable!("server never returns ok");
         }
     );
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+    if let Some(server) = serving {
+        let unserved = server.unserved(&challenges.http);
+        server.deregister(&challenges.http);
+        if res.is_err() && !unserved.is_empty() {
+            let ids: Vec<_> = unserved.iter().map(|c| c.id.as_str()).collect();
+            return res.map(|_| ()).with_note(|| {
+                format!(
+                    "The certificate authority never requested the http challenge for: {}",
+                    ids.join(", ")
+                )
+            });
+        }
+    }
+    let state = res?;
 
     if state.status == OrderStatus::Invalid {
         return Err(eyre::eyre!("order is invalid"))
             .suggestion("is the challenge server reachable?");
     }
     writeln!(stdout, ", done").unwrap();
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub(crate) async fn renew<P: PemItem>(
+    config: &Config,
+    http_server: &mut Option<Server>,
+    retry_after: &LastRetryAfter,
+    stdout: &mut (impl Write + Send),
+    debug: bool,
+) -> eyre::Result<Signed<P>> {
+    let (account, account_key) = account::get(config, retry_after).await?;
+    let mut order = order(&account, config)
+        .await
+        .wrap_err("Certificate authority can not issue a certificate")
+        .with_note(|| format!("names: {:?}", config.domains))?;
+
+    let challenges = prepare_challenges(config, &mut order).await?;
+    if let Some(dir) = &config.webroot {
+        webroot::write(dir, &challenges.http)?;
+    }
+    let provider = config
+        .dns
+        .as_ref()
+        .map(|dns| dns::Provider::new(config, dns));
+    let validated = match &provider {
+        Some(provider) if !challenges.dns.is_empty() => {
+            let res = match dns::present(provider, &challenges.dns, stdout).await {
+                Ok(()) => {
+                    validate(config, &mut order, &challenges, http_server, stdout, debug).await
+                }
+                Err(e) => Err(e),
+            };
+            dns::cleanup(provider, &challenges.dns, stdout).await;
+            res
+        }
+        _ => validate(config, &mut order, &challenges, http_server, stdout, debug).await,
+    };
+    if let Some(dir) = &config.webroot {
+        webroot::remove(dir, &challenges.http, stdout);
+    }
+    if validated.is_err() && order.state().status == OrderStatus::Invalid {
+        return Err(
+            authz::explain_invalid(config, &account_key, account.id(), order.state()).await,
+        );
+    }
+    validated?;
+
     write!(
         stdout,
         "waiting: certificate authority is signing our certificate"
@@ -246,31 +581,186 @@ pub async fn renew<P: PemItem>(
     .unwrap();
     stdout.flush().unwrap();
 
-    let names: Vec<String> = challenges.into_iter().map(|ch| ch.id).collect();
-    let (cert, csr) = prepare_sign_request(&names)?;
+    let (csr, private_key) = if let Some(csr) = &config.csr {
+        (csr.clone(), None)
+    } else {
+        let key_pair = match config.reuse_key.then(|| existing_key(config, stdout)) {
+            Some(Some(key_pair)) => key_pair,
+            _ => generate_key(config.key_type)?,
+        };
+        let (cert, csr) = prepare_sign_request(&config.domains, key_pair)?;
+        (csr, Some(cert.serialize_private_key_pem()))
+    };
 
-    order.finalize(&csr).await.unwrap();
-    let full_chain_pem = loop {
-        match order.certificate().await? {
-            Some(cert_chain_pem) => break cert_chain_pem,
-            None => sleep(Duration::from_secs(1)).await,
+    order
+        .finalize_csr(&csr)
+        .await
+        .wrap_err("Could not ask the certificate authority to sign the certificate")?;
+    let full_chain_pem = match order
+        .poll_certificate(&retry_policy(config.finalize_timeout))
+        .await
+    {
+        Ok(full_chain_pem) => full_chain_pem,
+        Err(e) => {
+            return Err(polling_failed(
+                e,
+                order.state(),
+                "the certificate to be signed",
+                "--finalize-timeout",
+            ))
         }
     };
+    let full_chain_pem = match &config.preferred_chain {
+        Some(issuer) if chain::top_issuer(&full_chain_pem).as_ref() != Some(issuer) => {
+            let cert_url = order
+                .state()
+                .certificate
+                .clone()
+                .expect("order with certificate always has a certificate url");
+            // the certificate is already issued, never lose it over the chain
+            match chain::alternate(config, &account_key, account.id(), &cert_url, issuer).await {
+                Ok(Some(alternate)) => alternate,
+                Ok(None) => {
+                    crate::warn!(
+                        stdout,
+                        "\nNo certificate chain issued by {issuer} offered, using the default chain"
+                    );
+                    full_chain_pem
+                }
+                Err(e) => {
+                    crate::warn!(
+                        stdout,
+                        "\nCould not get the alternate certificate chains, using the default chain: {e:#}"
+                    );
+                    full_chain_pem
+                }
+            }
+        }
+        _ => full_chain_pem,
+    };
 
-    server.abort();
     writeln!(stdout, ", done").unwrap();
-    Signed::from_key_and_fullchain(cert.serialize_private_key_pem(), full_chain_pem)
+    match private_key {
+        Some(key) => Signed::from_key_and_fullchain(key, full_chain_pem),
+        None => Signed::from_fullchain(full_chain_pem),
+    }
 }
 
-pub struct InstantAcme;
+/// Talks to the certificate authority. The http challenge server is kept
+/// running between requests, for example the staging check and the
+/// production request. It shuts down when this is dropped.
+#[derive(Debug, Default)]
+pub struct InstantAcme {
+    http_server: Option<Server>,
+}
+
+impl InstantAcme {
+    /// Stops the challenge server, waiting for open connections to close
+    pub async fn shutdown(self) -> eyre::Result<()> {
+        match self.http_server {
+            Some(server) => server.shutdown().await,
+            None => Ok(()),
+        }
+    }
+}
 
 impl ACME for InstantAcme {
     async fn renew<P: PemItem, W: Write + Send>(
-        &self,
+        &mut self,
         config: &Config,
         stdout: &mut W,
         debug: bool,
     ) -> eyre::Result<Signed<P>> {
-        renew(config, stdout, debug).await
+        let http_server = &mut self.http_server;
+        let last_retry_after = LastRetryAfter::default();
+        renew(config, http_server, &last_retry_after, stdout, debug)
+            .await
+            .map_err(|report| {
+                let retry_after = diagnostics::problem::find_problem(&report)
+                    .and_then(|problem| last_retry_after.for_problem(problem));
+                diagnostics::problem::improve_report(config, report, retry_after.as_ref())
+            })
+    }
+
+    async fn renewal_window(
+        &self,
+        config: &Config,
+        info: &CertInfo,
+    ) -> eyre::Result<Option<RenewalWindow>> {
+        ari::suggested_window(config, info).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::IsCa;
+
+    fn store_cert(config: &Config, key_pair: KeyPair) {
+        let mut params = CertificateParams::new(config.domains.clone());
+        params.alg = key_pair.algorithm();
+        params.key_pair = Some(key_pair);
+        let cert = Certificate::from_params(params).unwrap();
+
+        let mut params = CertificateParams::new(vec!["ca.example.org".to_owned()]);
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(params).unwrap();
+
+        let full_chain =
+            cert.serialize_pem_with_signer(&ca).unwrap() + &ca.serialize_pem().unwrap();
+        let signed: Signed<pem::Pem> =
+            Signed::from_key_and_fullchain(cert.serialize_private_key_pem(), full_chain).unwrap();
+        cert::store::on_disk(config, signed, &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn picks_challenge_per_authorization() {
+        use ChallengeType::{Dns01, Http01, TlsAlpn01};
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(42, dir.path());
+        let all = [Http01, Dns01, TlsAlpn01];
+
+        assert_eq!(pick_challenge(&config, "a", true, &all).unwrap(), Http01);
+        config.dns = Some(crate::config::DnsConfig::Manual);
+        assert_eq!(pick_challenge(&config, "a", true, &all).unwrap(), Dns01);
+        // an ip address in the same order still uses http
+        assert_eq!(pick_challenge(&config, "a", false, &all).unwrap(), Http01);
+        assert_eq!(
+            pick_challenge(&config, "a", true, &[Http01]).unwrap(),
+            Http01
+        );
+        config.tls_alpn = true;
+        assert_eq!(
+            pick_challenge(&config, "a", false, &all).unwrap(),
+            TlsAlpn01
+        );
+
+        let err = pick_challenge(&config, "a", false, &[Http01]).unwrap_err();
+        assert_eq!(err.to_string(), "No supported challenge offered for: a");
+    }
+
+    #[test]
+    fn reuses_existing_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::test(42, dir.path());
+        let key_pair = generate_key(KeyType::EcdsaP384).unwrap();
+        let public_key = key_pair.public_key_raw().to_vec();
+        store_cert(&config, key_pair);
+
+        let mut stdout = Vec::new();
+        let reused = existing_key(&config, &mut stdout).unwrap();
+        assert_eq!(reused.public_key_raw(), public_key);
+        assert_eq!(reused.algorithm(), &rcgen::PKCS_ECDSA_P384_SHA384);
+    }
+
+    #[test]
+    fn missing_key_warns() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::test(42, dir.path());
+
+        let mut stdout = Vec::new();
+        assert!(existing_key(&config, &mut stdout).is_none());
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.contains("Can not reuse private key"), "{stdout}");
     }
 }
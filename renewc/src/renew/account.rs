@@ -0,0 +1,396 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use instant_acme::{Account, AccountBuilder, AccountCredentials, ExternalAccountKey, NewAccount};
+use serde_json::json;
+
+use super::client::{self, LastRetryAfter};
+use super::jws::SigningKey;
+use crate::config::{AccountConfig, CaConfig, Config};
+
+/// One file per certificate authority (and environment). The name is
+/// derived from the directory url, e.g.
+/// `acme-v02.api.letsencrypt.org_directory.json`
+fn store_path(dir: &Path, directory_url: &str) -> PathBuf {
+    let without_scheme = directory_url
+        .split_once("://")
+        .map_or(directory_url, |(_, rest)| rest);
+    let name: String = without_scheme
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    dir.join(format!("{name}.json"))
+}
+
+fn load(path: &Path) -> eyre::Result<Option<AccountCredentials>> {
+    let json = match fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            tracing::debug!("No account credentials at {}", path.display());
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not read stored account credentials")
+                .with_note(|| format!("path: {path:?}"))
+        }
+        Ok(json) => json,
+    };
+
+    serde_json::from_str(&json)
+        .map(Some)
+        .wrap_err("Stored account credentials are corrupt")
+        .with_note(|| format!("path: {path:?}"))
+        .suggestion("Remove the file, a new account will then be created")
+}
+
+/// The credentials are written to a temporary file first that then replaces
+/// the stored ones. A failed write never leaves a half written file behind.
+fn store(path: &Path, credentials: &AccountCredentials) -> eyre::Result<()> {
+    let dir = path.parent().expect("store path is always in a dir");
+    fs::create_dir_all(dir)
+        .wrap_err("Could not create directory for account credentials")
+        .with_note(|| format!("path: {dir:?}"))
+        .suggestion("Use `--account-dir` to store the credentials elsewhere")?;
+
+    let json = serde_json::to_string_pretty(credentials)
+        .expect("account credentials are always serializable");
+    let mut temp = path.to_owned().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)
+        .wrap_err("Could not create account credentials file")
+        .with_note(|| format!("path: {temp:?}"))
+        .suggestion("Use `--account-dir` to store the credentials elsewhere")?;
+    // mode is only applied when the file is created
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+        .wrap_err("Could not restrict account credentials file permissions")
+        .with_note(|| format!("path: {temp:?}"))?;
+    file.write_all(json.as_bytes())
+        .and_then(|()| file.sync_all())
+        .wrap_err("Could not write account credentials")
+        .with_note(|| format!("path: {temp:?}"))?;
+    fs::rename(&temp, path)
+        .wrap_err("Could not replace the stored account credentials")
+        .with_note(|| format!("path: {path:?}"))
+}
+
+fn builder(ca: &CaConfig, retry_after: LastRetryAfter) -> eyre::Result<AccountBuilder> {
+    let client = client::new(ca)?;
+    Ok(Account::builder_with_http(Box::new(client::AcmeHttp(
+        client,
+        retry_after,
+    ))))
+}
+
+/// for the requests we sign ourselves
+fn signing_key(credentials: &AccountCredentials) -> eyre::Result<SigningKey> {
+    SigningKey::from_pkcs8(credentials.private_key().secret_pkcs8_der())
+        .wrap_err("Could not use account key")
+}
+
+async fn restore(
+    ca: &CaConfig,
+    url: &str,
+    path: &Path,
+    credentials: AccountCredentials,
+    retry_after: LastRetryAfter,
+) -> eyre::Result<(Account, SigningKey)> {
+    tracing::debug!("restoring account from: {}", path.display());
+    let key = signing_key(&credentials)?;
+    let account = builder(ca, retry_after)?
+        .from_credentials(credentials)
+        .await
+        .wrap_err("Could not restore account from stored credentials")
+        .with_note(|| format!("path: {path:?}"))
+        .with_note(|| format!("certificate authority: {url}"))?;
+    Ok((account, key))
+}
+
+// Restore the account for this certificate authority from its stored
+// credentials. Only if there are none do we create a new account.
+#[tracing::instrument(skip_all)]
+pub(super) async fn get(
+    config: &Config,
+    retry_after: &LastRetryAfter,
+) -> eyre::Result<(Account, SigningKey)> {
+    let url = config.ca.directory(config.production)?;
+    let path = store_path(&config.ca.account_dir, url);
+
+    if let Some(credentials) = load(&path)? {
+        return restore(&config.ca, url, &path, credentials, retry_after.clone()).await;
+    }
+
+    let contact = mailto(&config.email);
+    let eab = config
+        .ca
+        .eab
+        .as_ref()
+        .map(|eab| ExternalAccountKey::new(eab.kid.clone(), &eab.hmac_key));
+    let (account, credentials) = builder(&config.ca, retry_after.clone())?
+        .create(
+            &NewAccount {
+                contact: contact
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            url.to_owned(),
+            eab.as_ref(),
+        )
+        .await
+        .wrap_err("Could not create account with the certificate authority")
+        .with_note(|| format!("certificate authority: {url}"))?;
+
+    tracing::debug!("created new account, storing it at: {}", path.display());
+    store(&path, &credentials).wrap_err("Could not store new account")?;
+    Ok((account, signing_key(&credentials)?))
+}
+
+/// The stored account for this certificate authority and where it is
+/// stored. Unlike [`get`] this never creates an account.
+pub(super) async fn existing(
+    ca: &CaConfig,
+    production: bool,
+) -> eyre::Result<Option<(Account, SigningKey, PathBuf)>> {
+    let url = ca.directory(production)?;
+    let path = store_path(&ca.account_dir, url);
+    let Some(credentials) = load(&path)? else {
+        return Ok(None);
+    };
+    // errors of these accounts are not reported with their Retry-After
+    let (account, key) = restore(ca, url, &path, credentials, LastRetryAfter::default()).await?;
+    Ok(Some((account, key, path)))
+}
+
+/// There is nothing to manage if no account is stored
+async fn stored(config: &AccountConfig) -> eyre::Result<(Account, SigningKey, PathBuf)> {
+    if let Some(stored) = existing(&config.ca, config.production).await? {
+        return Ok(stored);
+    }
+
+    let url = config.ca.directory(config.production)?;
+    let path = store_path(&config.ca.account_dir, url);
+    Err(eyre::eyre!(
+        "No account stored for this certificate authority"
+    ))
+    .with_note(|| format!("path: {path:?}"))
+    .with_note(|| format!("certificate authority: {url}"))
+    .suggestion(
+        "An account is created the first time a certificate is requested. \
+            Pass the same `--account-dir` and `--production` as used then",
+    )
+}
+
+fn mailto(email: &[String]) -> Vec<String> {
+    email.iter().map(|addr| format!("mailto:{addr}")).collect()
+}
+
+/// Print the account as the certificate authority knows it
+#[tracing::instrument(skip(stdout))]
+pub async fn show(config: &AccountConfig, stdout: &mut impl Write) -> eyre::Result<()> {
+    let (account, key, path) = stored(config).await?;
+    let url = config.ca.directory(config.production)?;
+
+    // an empty update returns the account object (RFC 8555 section 7.3.3)
+    let client = client::new(&config.ca)?;
+    let new_nonce = client::new_nonce_url(&client, url).await?;
+    let payload = json!({});
+    let object = client::post_jws(&client, &new_nonce, account.id(), |nonce| {
+        key.jws(account.id(), nonce, Some(account.id()), Some(&payload))
+    })
+    .await?
+    .error_for_status()
+    .wrap_err("Could not get account from certificate authority")?
+    .json()?;
+
+    let contact = object["contact"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(serde_json::Value::as_str)
+        .map(|c| c.trim_start_matches("mailto:"))
+        .collect::<Vec<_>>();
+    let contact = if contact.is_empty() {
+        "none".to_owned()
+    } else {
+        contact.join(", ")
+    };
+    let status = object["status"].as_str().unwrap_or("unknown");
+
+    crate::info!(stdout, "account: {}", account.id());
+    crate::info!(stdout, "status: {status}");
+    crate::info!(stdout, "contact: {contact}");
+    crate::info!(stdout, "certificate authority: {url}");
+    crate::info!(stdout, "credentials: {}", path.display());
+    Ok(())
+}
+
+/// Replace the contact info with `email`, an empty list removes it
+#[tracing::instrument(skip(stdout))]
+pub async fn update_contact(
+    config: &AccountConfig,
+    email: &[String],
+    stdout: &mut impl Write,
+) -> eyre::Result<()> {
+    let (account, _, _) = stored(config).await?;
+    let contact = mailto(email);
+    let contact: Vec<_> = contact.iter().map(String::as_str).collect();
+    account
+        .update_contacts(&contact)
+        .await
+        .wrap_err("Could not update account contact info")?;
+
+    if email.is_empty() {
+        crate::info!(stdout, "removed all contact info from the account");
+    } else {
+        crate::info!(stdout, "account contact info is now: {}", email.join(", "));
+    }
+    Ok(())
+}
+
+/// Switch the account over to a newly generated key (RFC 8555 section 7.3.5)
+/// and store it in place of the old one.
+#[tracing::instrument(skip(stdout))]
+pub async fn key_rollover(config: &AccountConfig, stdout: &mut impl Write) -> eyre::Result<()> {
+    let (mut account, _, path) = stored(config).await?;
+    let credentials = account
+        .update_key()
+        .await
+        .wrap_err("Could not roll over the account key")?;
+
+    if let Err(report) = store(&path, &credentials) {
+        // the only copy of the new key, losing it locks us out of the account
+        let json = serde_json::to_string_pretty(&credentials)
+            .expect("account credentials are always serializable");
+        crate::error!(
+            stdout,
+            "could not store the new account credentials:\n{json}"
+        );
+        return Err(report)
+            .wrap_err("The account key was rolled over but the new key could not be stored")
+            .note("The old key stored for the account no longer works")
+            .suggestion(format!(
+                "Save the credentials printed above to: {}",
+                path.display()
+            ));
+    }
+    crate::info!(
+        stdout,
+        "rolled over account key, stored new key at: {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Deactivate the account (RFC 8555 section 7.3.6). Its credentials are kept
+/// with `.deactivated` appended so a new account is created on the next run.
+#[tracing::instrument(skip(stdout))]
+pub async fn deactivate(config: &AccountConfig, stdout: &mut impl Write) -> eyre::Result<()> {
+    let (account, _, path) = stored(config).await?;
+    let id = account.id().to_owned();
+    account
+        .deactivate()
+        .await
+        .wrap_err("Could not deactivate account")?;
+    crate::info!(stdout, "deactivated account: {id}");
+
+    let mut deactivated = path.clone().into_os_string();
+    deactivated.push(".deactivated");
+    fs::rename(&path, &deactivated)
+        .wrap_err("Could not move credentials of the deactivated account")
+        .with_note(|| format!("path: {path:?}"))
+        .suggestion("Remove the file, it can not be used anymore")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_path_per_directory() {
+        let dir = Path::new("/state");
+        assert_eq!(
+            store_path(dir, "https://acme-v02.api.letsencrypt.org/directory"),
+            Path::new("/state/acme-v02.api.letsencrypt.org_directory.json")
+        );
+        assert_eq!(
+            store_path(dir, "https://localhost:14000/dir/"),
+            Path::new("/state/localhost_14000_dir.json")
+        );
+    }
+
+    #[test]
+    fn stored_credentials_are_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts").join("ca.json");
+        let credentials: AccountCredentials = serde_json::from_str(
+            r#"{"id":"https://ca.test/acct/1","key_pkcs8":"AAAA","directory":"https://ca.test/dir"}"#,
+        )
+        .unwrap();
+
+        store(&path, &credentials).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded.private_key(), credentials.private_key());
+        assert!(load(&dir.path().join("missing.json")).unwrap().is_none());
+    }
+
+    #[test]
+    fn store_replaces_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.json");
+        let credentials = |id: &str| -> AccountCredentials {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "key_pkcs8": "AAAA",
+                "directory": "https://ca.test/dir",
+            }))
+            .unwrap()
+        };
+
+        store(&path, &credentials("https://ca.test/acct/1")).unwrap();
+        store(&path, &credentials("https://ca.test/acct/2")).unwrap();
+        let stored = fs::read_to_string(&path).unwrap();
+        assert!(stored.contains("https://ca.test/acct/2"), "{stored}");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn manage_without_stored_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AccountConfig {
+            ca: CaConfig {
+                account_dir: dir.path().to_owned(),
+                ..CaConfig::default()
+            },
+            production: false,
+        };
+
+        let err = stored(&config).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "No account stored for this certificate authority"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use instant_acme::{self as acme, CertificateIdentifier};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use super::account;
+use crate::cert::info::{Info, RenewalWindow};
+use crate::config::{name, Config};
+
+/// The last renewal information, the certificate authority asks us to wait
+/// with fetching it again until `fetch_after` (its Retry-After).
+#[derive(Debug, Serialize, Deserialize)]
+struct Cached {
+    cert_id: String,
+    /// unix timestamps
+    start: i64,
+    end: i64,
+    explanation_url: Option<String>,
+    fetch_after: i64,
+}
+
+impl Cached {
+    fn window(&self) -> eyre::Result<RenewalWindow> {
+        Ok(RenewalWindow {
+            start: OffsetDateTime::from_unix_timestamp(self.start)?,
+            end: OffsetDateTime::from_unix_timestamp(self.end)?,
+            explanation_url: self.explanation_url.clone(),
+        })
+    }
+}
+
+fn cache_path(config: &Config) -> eyre::Result<PathBuf> {
+    Ok(config
+        .ca
+        .account_dir
+        .join("renewal_info")
+        .join(format!("{}.json", name(&config.domains)?)))
+}
+
+fn load_cached(config: &Config, cert_id: &CertificateIdentifier) -> Option<RenewalWindow> {
+    let path = cache_path(config).ok()?;
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::debug!("could not read cached renewal information: {e}");
+            return None;
+        }
+    };
+    let cached: Cached = serde_json::from_str(&json).ok()?;
+    let fresh = OffsetDateTime::now_utc().unix_timestamp() < cached.fetch_after;
+    if cached.cert_id != cert_id.to_string() || !fresh {
+        return None;
+    }
+    cached.window().ok()
+}
+
+fn store_cached(
+    config: &Config,
+    cert_id: &CertificateIdentifier,
+    window: &RenewalWindow,
+    retry_after: std::time::Duration,
+) -> eyre::Result<()> {
+    let retry_after = Duration::try_from(retry_after).unwrap_or(Duration::ZERO);
+    let cached = Cached {
+        cert_id: cert_id.to_string(),
+        start: window.start.unix_timestamp(),
+        end: window.end.unix_timestamp(),
+        explanation_url: window.explanation_url.clone(),
+        fetch_after: (OffsetDateTime::now_utc() + retry_after).unix_timestamp(),
+    };
+    let path = cache_path(config)?;
+    let dir = path.parent().expect("cache path is always in a dir");
+    fs::create_dir_all(dir)
+        .wrap_err("Could not create directory for renewal information")
+        .with_note(|| format!("path: {dir:?}"))?;
+    let json = serde_json::to_string_pretty(&cached).expect("always serializable");
+    fs::write(&path, json)
+        .wrap_err("Could not store renewal information")
+        .with_note(|| format!("path: {path:?}"))
+}
+
+/// Ask the certificate authority when to renew the certificate using ACME
+/// Renewal Information (RFC 9773). Returns None if the certificate
+/// authority does not support that or does not know the certificate.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn suggested_window(
+    config: &Config,
+    info: &Info,
+) -> eyre::Result<Option<RenewalWindow>> {
+    let Some(cert_id) = &info.ari_cert_id else {
+        return Ok(None);
+    };
+    if let Some(window) = load_cached(config, cert_id) {
+        tracing::debug!("using cached renewal information");
+        return Ok(Some(window));
+    }
+
+    // renewal information is not tied to an account, instant-acme only
+    // needs one to find the directory
+    let Some((account, _, _)) = account::existing(&config.ca, config.production).await? else {
+        tracing::debug!("no account stored, not checking renewal information");
+        return Ok(None);
+    };
+    let (renewal_info, retry_after) = match account.renewal_info(cert_id).await {
+        Ok(res) => res,
+        Err(acme::Error::Unsupported(_)) => {
+            tracing::debug!("certificate authority does not support renewal information");
+            return Ok(None);
+        }
+        Err(acme::Error::Api(problem)) if problem.status == Some(404) => {
+            tracing::debug!("certificate authority does not know the certificate");
+            return Ok(None);
+        }
+        Err(e) => return Err(e).wrap_err("Could not get renewal information"),
+    };
+
+    let window = RenewalWindow {
+        start: renewal_info.suggested_window.start,
+        end: renewal_info.suggested_window.end,
+        explanation_url: renewal_info.explanation_url,
+    };
+    if let Err(e) = store_cached(config, cert_id, &window, retry_after) {
+        tracing::warn!("{e:#}");
+    }
+    Ok(Some(window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_honours_retry_after() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(80, dir.path());
+        config.ca.account_dir = dir.path().join("accounts");
+        let cert_id = CertificateIdentifier::new(
+            rustls::pki_types::Der::from_slice(b"key id"),
+            rustls::pki_types::Der::from_slice(b"serial"),
+        );
+        let now = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        let window = RenewalWindow {
+            start: now,
+            end: now + Duration::days(1),
+            explanation_url: None,
+        };
+
+        store_cached(
+            &config,
+            &cert_id,
+            &window,
+            std::time::Duration::from_secs(3600),
+        )
+        .unwrap();
+        assert_eq!(load_cached(&config, &cert_id), Some(window.clone()));
+
+        store_cached(&config, &cert_id, &window, std::time::Duration::ZERO).unwrap();
+        assert_eq!(load_cached(&config, &cert_id), None, "retry after passed");
+    }
+}
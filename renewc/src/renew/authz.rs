@@ -0,0 +1,226 @@
+use std::fmt::Write as _;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::{Help, Report};
+use instant_acme::{OrderState, Problem};
+use serde::Deserialize;
+
+use super::client;
+use super::jws::SigningKey;
+use crate::config::Config;
+
+/// Authorization object (RFC 8555 section 7.1.4). Instant-acme does not
+/// keep the validation records so we fetch and parse it ourselves.
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    identifier: Identifier,
+    status: String,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+    #[serde(default)]
+    wildcard: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Challenge {
+    r#type: String,
+    status: String,
+    error: Option<Problem>,
+    /// What the certificate authority did to validate the challenge, not
+    /// standardized. This follows Let's Encrypt (Boulder) and Pebble.
+    #[serde(default)]
+    validation_record: Vec<ValidationRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidationRecord {
+    url: Option<String>,
+    hostname: Option<String>,
+    port: Option<String>,
+    #[serde(default)]
+    addresses_resolved: Vec<String>,
+    address_used: Option<String>,
+}
+
+impl Authorization {
+    fn name(&self) -> String {
+        if self.wildcard {
+            format!("*.{}", self.identifier.value)
+        } else {
+            self.identifier.value.clone()
+        }
+    }
+
+    fn failed_challenges(&self) -> impl Iterator<Item = &Challenge> {
+        self.challenges
+            .iter()
+            .filter(|c| c.error.is_some() || c.status == "invalid")
+    }
+}
+
+/// What went wrong for the authorization and what the certificate authority
+/// saw while validating it
+fn describe(authz: &Authorization) -> String {
+    let mut text = format!("{} is {}", authz.name(), authz.status);
+    for challenge in authz.failed_challenges() {
+        let problem = challenge.error.as_ref();
+        let detail = problem
+            .and_then(|p| p.detail.as_deref())
+            .unwrap_or("no details");
+        let _ = write!(text, "\n\t{}: {detail}", challenge.r#type);
+        if let Some(ty) = problem.and_then(|p| p.r#type.as_deref()) {
+            let _ = write!(text, "\n\t  problem type: {ty}");
+        }
+
+        for record in &challenge.validation_record {
+            if let Some(url) = &record.url {
+                let _ = write!(text, "\n\t  fetched: {url}");
+            }
+            if let (Some(host), Some(port)) = (&record.hostname, &record.port) {
+                let _ = write!(text, "\n\t  host: {host}:{port}");
+            }
+            if !record.addresses_resolved.is_empty() {
+                let resolved = record.addresses_resolved.join(", ");
+                let _ = write!(text, "\n\t  resolved to: {resolved}");
+            }
+            if let Some(address) = &record.address_used {
+                let _ = write!(text, "\n\t  connected to: {address}");
+            }
+        }
+    }
+    text
+}
+
+async fn fetch(
+    client: &client::Client,
+    new_nonce: &str,
+    key: &SigningKey,
+    account_id: &str,
+    url: &str,
+) -> eyre::Result<Authorization> {
+    let response = client::post_jws(client, new_nonce, url, |nonce| {
+        key.jws(url, nonce, Some(account_id), None)
+    })
+    .await?
+    .error_for_status()?;
+    serde_json::from_slice(&response.body)
+        .wrap_err("Certificate authority sent an invalid authorization")
+}
+
+/// Explain why the order became invalid using the errors and validation
+/// records of its authorizations.
+#[tracing::instrument(skip_all)]
+pub(super) async fn explain_invalid(
+    config: &Config,
+    key: &SigningKey,
+    account_id: &str,
+    state: &OrderState,
+) -> Report {
+    let mut failed = Vec::new();
+    let mut fetch_errors = Vec::new();
+    match client::new(&config.ca) {
+        Ok(client) => {
+            let directory = config.ca.directory(config.production);
+            let new_nonce = match directory {
+                Ok(directory) => client::new_nonce_url(&client, directory).await,
+                Err(e) => Err(e),
+            };
+            match new_nonce {
+                Ok(new_nonce) => {
+                    for authz in &state.authorizations {
+                        match fetch(&client, &new_nonce, key, account_id, &authz.url).await {
+                            Ok(authz) if authz.status != "valid" => failed.push(authz),
+                            Ok(_) => (),
+                            Err(e) => fetch_errors.push(e),
+                        }
+                    }
+                }
+                Err(e) => fetch_errors.push(e),
+            }
+        }
+        Err(e) => fetch_errors.push(e),
+    }
+
+    let first_problem = failed
+        .iter()
+        .flat_map(Authorization::failed_challenges)
+        .find_map(|c| c.error.clone())
+        .or_else(|| state.error.clone());
+    let mut report = match first_problem {
+        Some(problem) => Report::new(problem).wrap_err("order is invalid"),
+        None => eyre::eyre!("order is invalid"),
+    };
+
+    if failed.is_empty() {
+        report = report.suggestion(
+            "sometimes this happens when the challenge server is not reachable. \
+            Try the debug flag to investigate",
+        );
+    }
+    for authz in &failed {
+        let description = describe(authz);
+        report = report.with_note(|| format!("authorization for {description}"));
+    }
+    for e in fetch_errors {
+        report = report.with_warning(|| format!("Could not get authorization details: {e:#}"));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_failed_http_challenge() {
+        let authz: Authorization = serde_json::from_str(
+            r#"{
+                "identifier": {"type": "dns", "value": "example.org"},
+                "status": "invalid",
+                "challenges": [{
+                    "type": "http-01",
+                    "url": "https://ca.test/chall/1",
+                    "token": "abc",
+                    "status": "invalid",
+                    "error": {
+                        "type": "urn:ietf:params:acme:error:connection",
+                        "detail": "Timeout during connect (likely firewall problem)",
+                        "status": 400
+                    },
+                    "validationRecord": [{
+                        "url": "http://example.org/.well-known/acme-challenge/abc",
+                        "hostname": "example.org",
+                        "port": "80",
+                        "addressesResolved": ["192.0.2.1", "2001:db8::1"],
+                        "addressUsed": "2001:db8::1"
+                    }]
+                }, {
+                    "type": "dns-01",
+                    "url": "https://ca.test/chall/2",
+                    "token": "abc",
+                    "status": "pending"
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let description = describe(&authz);
+        assert_eq!(
+            description,
+            "example.org is invalid\n\
+            \thttp-01: Timeout during connect (likely firewall problem)\n\
+            \t  problem type: urn:ietf:params:acme:error:connection\n\
+            \t  fetched: http://example.org/.well-known/acme-challenge/abc\n\
+            \t  host: example.org:80\n\
+            \t  resolved to: 192.0.2.1, 2001:db8::1\n\
+            \t  connected to: 2001:db8::1"
+        );
+    }
+}
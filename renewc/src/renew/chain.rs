@@ -0,0 +1,127 @@
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use http::HeaderMap;
+use x509_parser::prelude::Pem;
+
+use super::client;
+use super::jws::SigningKey;
+use crate::config::Config;
+
+/// Common name of the issuer of the topmost certificate in the chain
+pub(super) fn top_issuer(full_chain: &str) -> Option<String> {
+    let top = Pem::iter_from_buffer(full_chain.as_bytes())
+        .filter_map(Result::ok)
+        .last()?;
+    let top = top.parse_x509().ok()?;
+    let issuer = top
+        .issuer()
+        .iter_common_name()
+        .find_map(|cn| cn.as_str().ok())
+        .map(str::to_owned);
+    issuer
+}
+
+/// urls from `Link: <url>;rel="alternate"` headers (RFC 8555 section 7.4.2)
+fn alternates(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(http::header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|link| {
+            let (url, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+            params
+                .split(';')
+                .filter_map(|param| param.trim().strip_prefix("rel="))
+                .any(|rel| rel.trim_matches('"') == "alternate")
+                .then(|| url.to_owned())
+        })
+        .collect()
+}
+
+/// Look through the alternate chains offered for the certificate for one
+/// whose topmost certificate is issued by `issuer`. Returns the full chain
+/// if one is found.
+#[tracing::instrument(skip(config, key))]
+pub(super) async fn alternate(
+    config: &Config,
+    key: &SigningKey,
+    account_id: &str,
+    cert_url: &str,
+    issuer: &str,
+) -> eyre::Result<Option<String>> {
+    let client = client::new(&config.ca)?;
+    let directory = config.ca.directory(config.production)?;
+    let new_nonce = client::new_nonce_url(&client, directory).await?;
+
+    let download = |url: String| {
+        let client = &client;
+        let new_nonce = new_nonce.as_str();
+        async move {
+            client::post_jws(client, new_nonce, &url, |nonce| {
+                key.jws(&url, nonce, Some(account_id), None)
+            })
+            .await?
+            .error_for_status()
+            .wrap_err("Could not download certificate chain")
+            .with_note(|| format!("url: {url}"))
+        }
+    };
+
+    let default = download(cert_url.to_owned()).await?;
+    for url in alternates(&default.headers) {
+        let chain = download(url).await?;
+        let chain = String::from_utf8(chain.body.to_vec())
+            .wrap_err("Certificate chain is not valid utf8")?;
+        let top = top_issuer(&chain);
+        tracing::debug!("alternate chain topmost issuer: {top:?}");
+        if top.as_deref() == Some(issuer) {
+            return Ok(Some(chain));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{Certificate, CertificateParams, DnType, IsCa};
+
+    #[test]
+    fn link_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            http::header::LINK,
+            r#"<https://ca.example/directory>;rel="index""#.parse().unwrap(),
+        );
+        headers.append(
+            http::header::LINK,
+            r#"<https://ca.example/cert/1/1>;rel="alternate", <https://ca.example/cert/1/2> ; rel=alternate"#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            alternates(&headers),
+            ["https://ca.example/cert/1/1", "https://ca.example/cert/1/2"]
+        );
+    }
+
+    fn ca(name: &str) -> Certificate {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        Certificate::from_params(params).unwrap()
+    }
+
+    #[test]
+    fn topmost_issuer() {
+        let root = ca("Example Root X1");
+        let intermediate = ca("Example R1");
+        let leaf =
+            Certificate::from_params(CertificateParams::new(vec!["example.org".into()])).unwrap();
+
+        let chain = leaf.serialize_pem_with_signer(&intermediate).unwrap()
+            + &intermediate.serialize_pem_with_signer(&root).unwrap();
+        assert_eq!(top_issuer(&chain).as_deref(), Some("Example Root X1"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use http::header::{CONTENT_TYPE, RETRY_AFTER, USER_AGENT};
+use http::{Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use instant_acme::{self as acme, BodyWrapper, BytesResponse, HttpClient};
+use rustls::pki_types::CertificateDer;
+
+use crate::config::CaConfig;
+
+const APP: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Https client for talking to the certificate authority. Also used by
+/// instant-acme so the same roots are trusted everywhere.
+pub(crate) type Client =
+    hyper_util::client::legacy::Client<HttpsConnector<HttpConnector>, BodyWrapper<Bytes>>;
+
+/// Retry-After header (RFC 9110 section 10.2.3) of an error response
+#[derive(Debug, Clone)]
+pub(crate) struct RetryAfter {
+    pub(crate) received: SystemTime,
+    /// either a number of seconds or a http date
+    pub(crate) value: String,
+}
+
+/// A Retry-After and the problem document it was sent with
+#[derive(Debug)]
+struct ProblemRetryAfter {
+    r#type: Option<String>,
+    detail: Option<String>,
+    retry_after: RetryAfter,
+}
+
+fn has_retry_after(parts: &http::response::Parts) -> bool {
+    !parts.status.is_success() && parts.headers.contains_key(RETRY_AFTER)
+}
+
+/// The last Retry-After received by an [`AcmeHttp`], shared with whoever
+/// reports its errors. Instant-acme does not expose the headers of error
+/// responses. We only need the Retry-After to tell when a rate limit resets.
+/// It is remembered along with its problem document so it is never
+/// attributed to another error.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LastRetryAfter(Arc<Mutex<Option<ProblemRetryAfter>>>);
+
+impl LastRetryAfter {
+    fn record(&self, parts: &http::response::Parts, body: &[u8]) {
+        if !has_retry_after(parts) {
+            return;
+        }
+        let Some(value) = parts.headers.get(RETRY_AFTER).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+        let Ok(problem) = serde_json::from_slice::<acme::Problem>(body) else {
+            return;
+        };
+        *self.0.lock().expect("never poisoned") = Some(ProblemRetryAfter {
+            r#type: problem.r#type,
+            detail: problem.detail,
+            retry_after: RetryAfter {
+                received: SystemTime::now(),
+                value: value.trim().to_owned(),
+            },
+        });
+    }
+
+    /// The Retry-After the certificate authority sent along with `problem`
+    pub(crate) fn for_problem(&self, problem: &acme::Problem) -> Option<RetryAfter> {
+        self.0
+            .lock()
+            .expect("never poisoned")
+            .as_ref()
+            .filter(|last| last.r#type == problem.r#type && last.detail == problem.detail)
+            .map(|last| last.retry_after.clone())
+    }
+}
+
+/// The [`Client`] as used by instant-acme, records the Retry-After of error
+/// responses.
+pub(crate) struct AcmeHttp(pub(crate) Client, pub(crate) LastRetryAfter);
+
+impl HttpClient for AcmeHttp {
+    fn request(
+        &self,
+        req: Request<BodyWrapper<Bytes>>,
+    ) -> Pin<Box<dyn Future<Output = Result<BytesResponse, acme::Error>> + Send>> {
+        let response = HttpClient::request(&self.0, req);
+        let last_retry_after = self.1.clone();
+        Box::pin(async move {
+            let mut response = response.await?;
+            if has_retry_after(&response.parts) {
+                let body = response
+                    .body
+                    .into_bytes()
+                    .await
+                    .map_err(acme::Error::Other)?;
+                last_retry_after.record(&response.parts, &body);
+                response.body = Box::new(body);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn load_root(path: &Path) -> eyre::Result<rustls::RootCertStore> {
+    let pem = std::fs::read(path).wrap_err("Could not read file")?;
+    let pem = pem::parse(pem).wrap_err("Not a valid PEM file")?;
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(pem.into_contents()))
+        .wrap_err("Not a valid certificate")?;
+    Ok(roots)
+}
+
+pub(crate) fn new(ca: &CaConfig) -> eyre::Result<Client> {
+    let builder = match &ca.root {
+        Some(root) => {
+            let roots = load_root(root)
+                .wrap_err("Could not load root certificate for the certificate authority")
+                .with_note(|| format!("path: {}", root.display()))?;
+            HttpsConnectorBuilder::new().with_tls_config(
+                rustls::ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        }
+        None => HttpsConnectorBuilder::new()
+            .try_with_platform_verifier()
+            .wrap_err("Could not load the system's root certificates")?,
+    };
+    let connector = builder.https_only().enable_http1().enable_http2().build();
+    Ok(hyper_util::client::legacy::Client::builder(TokioExecutor::new()).build(connector))
+}
+
+pub(crate) struct Response {
+    pub status: StatusCode,
+    pub headers: http::HeaderMap,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub(crate) fn json(&self) -> eyre::Result<serde_json::Value> {
+        serde_json::from_slice(&self.body).wrap_err("Certificate authority sent invalid json")
+    }
+
+    /// Turns a problem document (RFC 7807) into an error
+    pub(crate) fn error_for_status(self) -> eyre::Result<Self> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+        let report = match serde_json::from_slice::<acme::Problem>(&self.body) {
+            Ok(problem) => eyre::Report::new(problem),
+            Err(_) => eyre::eyre!("no details"),
+        };
+        Err(report).wrap_err(format!(
+            "Certificate authority responded with: {}",
+            self.status
+        ))
+    }
+
+    pub(crate) fn nonce(&self) -> Option<String> {
+        self.headers
+            .get("replay-nonce")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    }
+}
+
+async fn send(client: &Client, request: Request<BodyWrapper<Bytes>>) -> eyre::Result<Response> {
+    let uri = request.uri().to_string();
+    let mut response = HttpClient::request(client, request)
+        .await
+        .wrap_err("Could not reach the certificate authority")
+        .with_note(|| format!("url: {uri}"))?;
+    let body = response
+        .body
+        .into_bytes()
+        .await
+        .map_err(|e| eyre::eyre!(e))
+        .wrap_err("Could not read response from the certificate authority")?;
+    Ok(Response {
+        status: response.parts.status,
+        headers: response.parts.headers,
+        body,
+    })
+}
+
+pub(crate) async fn get(client: &Client, url: &str) -> eyre::Result<Response> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        .header(USER_AGENT, APP)
+        .body(BodyWrapper::default())
+        .wrap_err("Invalid url")?;
+    send(client, request).await
+}
+
+/// The directory object (RFC 8555 section 7.1.1) of the certificate
+/// authority
+pub(crate) async fn directory(client: &Client, url: &str) -> eyre::Result<serde_json::Value> {
+    get(client, url)
+        .await?
+        .error_for_status()?
+        .json()
+        .wrap_err("Could not get directory from certificate authority")
+        .with_note(|| format!("directory: {url}"))
+}
+
+/// Where to get a fresh nonce (RFC 8555 section 7.2)
+pub(crate) async fn new_nonce_url(client: &Client, directory_url: &str) -> eyre::Result<String> {
+    directory(client, directory_url).await?["newNonce"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| eyre::eyre!("Certificate authority directory has no newNonce"))
+        .with_note(|| format!("directory: {directory_url}"))
+}
+
+pub(crate) async fn new_nonce(client: &Client, url: &str) -> eyre::Result<String> {
+    let request = Request::builder()
+        .method(Method::HEAD)
+        .uri(url)
+        .header(USER_AGENT, APP)
+        .body(BodyWrapper::default())
+        .wrap_err("Invalid url")?;
+    send(client, request)
+        .await?
+        .nonce()
+        .ok_or_else(|| eyre::eyre!("Certificate authority did not provide a nonce"))
+}
+
+/// Post a JWS created by `sign` for a nonce. A nonce can be rejected, the
+/// response then contains a fresh one which we retry with.
+pub(crate) async fn post_jws(
+    client: &Client,
+    new_nonce_url: &str,
+    url: &str,
+    sign: impl Fn(&str) -> eyre::Result<Vec<u8>>,
+) -> eyre::Result<Response> {
+    let mut nonce = new_nonce(client, new_nonce_url).await?;
+    for _ in 0..3 {
+        let response = post(client, url, sign(&nonce)?).await?;
+        let bad_nonce = !response.status.is_success()
+            && response
+                .json()
+                .is_ok_and(|problem| problem["type"] == "urn:ietf:params:acme:error:badNonce");
+        match response.nonce() {
+            Some(fresh) if bad_nonce => nonce = fresh,
+            _ => return Ok(response),
+        }
+    }
+    Err(eyre::eyre!(
+        "Certificate authority kept rejecting our nonce"
+    ))
+}
+
+/// Post a signed (JWS) body
+async fn post(client: &Client, url: &str, jws: Vec<u8>) -> eyre::Result<Response> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(USER_AGENT, APP)
+        .header(CONTENT_TYPE, "application/jose+json")
+        .body(BodyWrapper::from(jws))
+        .wrap_err("Invalid url")?;
+    send(client, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(detail: &str) -> acme::Problem {
+        serde_json::from_value(serde_json::json!({
+            "type": "urn:ietf:params:acme:error:rateLimited",
+            "detail": detail,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn retry_after_only_for_its_problem() {
+        let (parts, ()) = http::Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, "60")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body = br#"{"type": "urn:ietf:params:acme:error:rateLimited", "detail": "too many"}"#;
+        let last = LastRetryAfter::default();
+        last.record(&parts, body);
+
+        let retry_after = last.for_problem(&problem("too many")).unwrap();
+        assert_eq!(retry_after.value, "60");
+        assert!(last.for_problem(&problem("something else")).is_none());
+
+        let other = LastRetryAfter::default();
+        assert!(other.for_problem(&problem("too many")).is_none());
+    }
+}
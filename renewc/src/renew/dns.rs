@@ -0,0 +1,219 @@
+use std::io::Write;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+
+use crate::config::{Config, DnsConfig};
+
+mod exec;
+mod manual;
+mod rfc2136;
+
+/// The TXT record the certificate authority looks up to verify
+/// we control `domain`.
+#[derive(Debug, Clone)]
+pub struct TxtRecord {
+    /// domain being validated, without any wildcard prefix
+    pub domain: String,
+    /// always `_acme-challenge.<domain>`
+    pub name: String,
+    pub value: String,
+}
+
+impl TxtRecord {
+    #[must_use]
+    pub fn new(domain: &str, value: String) -> Self {
+        Self {
+            domain: domain.to_owned(),
+            name: format!("_acme-challenge.{domain}"),
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dns01Challenge {
+    pub url: String,
+    pub record: TxtRecord,
+    pub id: String,
+}
+
+/// Something that can publish the TXT records needed for dns-01 challenges.
+pub trait DnsProvider {
+    #[allow(async_fn_in_trait)]
+    async fn create(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()>;
+    #[allow(async_fn_in_trait)]
+    async fn cleanup(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()>;
+}
+
+pub enum Provider {
+    Manual(manual::Manual),
+    Rfc2136(rfc2136::Rfc2136),
+    Exec(exec::Exec),
+}
+
+impl Provider {
+    #[must_use]
+    pub fn new(config: &Config, dns: &DnsConfig) -> Self {
+        match dns {
+            DnsConfig::Manual => Self::Manual(manual::Manual::new(config)),
+            DnsConfig::Rfc2136(rfc2136) => Self::Rfc2136(rfc2136::Rfc2136::new(rfc2136.clone())),
+            DnsConfig::Exec(exec) => Self::Exec(exec::Exec::new(exec.clone())),
+        }
+    }
+}
+
+impl DnsProvider for Provider {
+    async fn create(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        match self {
+            Provider::Manual(p) => p.create(record, stdout).await,
+            Provider::Rfc2136(p) => p.create(record, stdout).await,
+            Provider::Exec(p) => p.create(record, stdout).await,
+        }
+    }
+
+    async fn cleanup(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        match self {
+            Provider::Manual(p) => p.cleanup(record, stdout).await,
+            Provider::Rfc2136(p) => p.cleanup(record, stdout).await,
+            Provider::Exec(p) => p.cleanup(record, stdout).await,
+        }
+    }
+}
+
+pub(super) async fn present(
+    provider: &impl DnsProvider,
+    challenges: &[Dns01Challenge],
+    stdout: &mut (impl Write + Send),
+) -> eyre::Result<()> {
+    for Dns01Challenge { record, .. } in challenges {
+        provider
+            .create(record, stdout)
+            .await
+            .wrap_err("Could not create dns challenge record")
+            .with_note(|| format!("record: {} TXT \"{}\"", record.name, record.value))?;
+    }
+    Ok(())
+}
+
+/// Failing to clean up does not affect the certificate, therefore
+/// this only warns.
+pub(super) async fn cleanup(
+    provider: &impl DnsProvider,
+    challenges: &[Dns01Challenge],
+    stdout: &mut (impl Write + Send),
+) {
+    for Dns01Challenge { record, .. } in challenges {
+        if let Err(e) = provider.cleanup(record, stdout).await {
+            crate::warn!(
+                stdout,
+                "Could not remove dns challenge record {}: {e:#}",
+                record.name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Fails to create the record for `broken`, logs every call
+    struct Flaky {
+        broken: &'static str,
+        log: Mutex<Vec<String>>,
+    }
+
+    impl DnsProvider for Flaky {
+        async fn create(
+            &self,
+            record: &TxtRecord,
+            _stdout: &mut (impl Write + Send),
+        ) -> eyre::Result<()> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("create {}", record.domain));
+            if record.domain == self.broken {
+                return Err(eyre::eyre!("api said no"));
+            }
+            Ok(())
+        }
+
+        async fn cleanup(
+            &self,
+            record: &TxtRecord,
+            _stdout: &mut (impl Write + Send),
+        ) -> eyre::Result<()> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("cleanup {}", record.domain));
+            if record.domain == self.broken {
+                return Err(eyre::eyre!("no such record"));
+            }
+            Ok(())
+        }
+    }
+
+    fn challenges(domains: &[&str]) -> Vec<Dns01Challenge> {
+        domains
+            .iter()
+            .map(|domain| Dns01Challenge {
+                url: String::new(),
+                record: TxtRecord::new(domain, "value".to_owned()),
+                id: (*domain).to_owned(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn failed_present_is_cleaned_up() {
+        let provider = Flaky {
+            broken: "b.example.org",
+            log: Mutex::new(Vec::new()),
+        };
+        let challenges = challenges(&["a.example.org", "b.example.org", "c.example.org"]);
+        let mut stdout = Vec::new();
+
+        let err = present(&provider, &challenges, &mut stdout)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Could not create dns challenge record");
+        cleanup(&provider, &challenges, &mut stdout).await;
+
+        assert_eq!(
+            *provider.log.lock().unwrap(),
+            [
+                "create a.example.org",
+                "create b.example.org",
+                "cleanup a.example.org",
+                "cleanup b.example.org",
+                "cleanup c.example.org",
+            ]
+        );
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(
+            stdout.contains("Could not remove dns challenge record _acme-challenge.b.example.org"),
+            "{stdout}"
+        );
+    }
+}
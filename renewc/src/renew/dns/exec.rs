@@ -0,0 +1,195 @@
+use std::io::Write;
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::{Help, SectionExt};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+use super::{DnsProvider, TxtRecord};
+use crate::config::ExecConfig;
+
+pub struct Exec {
+    config: ExecConfig,
+}
+
+impl Exec {
+    pub(super) fn new(config: ExecConfig) -> Self {
+        Self { config }
+    }
+}
+
+/// A hook that hangs (waiting on a slow api, asking for input) should not
+/// block renewing forever
+const HOOK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Kills the hook and everything it started, those could otherwise still
+/// change the record after we moved on.
+fn kill_group(pgid: u32) {
+    let Ok(pgid) = libc::pid_t::try_from(pgid) else {
+        return;
+    };
+    // Safety: killpg only sends a signal, it does not touch our memory
+    if unsafe { libc::killpg(pgid, libc::SIGKILL) } != 0 {
+        tracing::debug!(
+            "could not kill hook process group: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[tracing::instrument(skip(record))]
+async fn run_hook(hook: &str, record: &TxtRecord, timeout: Duration) -> eyre::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("RENEWC_DOMAIN", &record.domain)
+        .env("RENEWC_RECORD_NAME", &record.name)
+        .env("RENEWC_TXT_VALUE", &record.value)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // its own group so anything the hook starts can be killed too
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err("Could not run hook")
+        .with_note(|| format!("hook: {hook}"))?;
+    let pgid = child.id().expect("not yet waited on");
+
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    // the output read so far is kept when the timeout cancels this
+    let finished = tokio::time::timeout(timeout, async {
+        tokio::try_join!(
+            child.wait(),
+            stdout_pipe.read_to_end(&mut stdout),
+            stderr_pipe.read_to_end(&mut stderr),
+        )
+    })
+    .await;
+
+    let stdout = String::from_utf8_lossy(&stdout).trim().to_owned();
+    let stderr = String::from_utf8_lossy(&stderr).trim().to_owned();
+    tracing::debug!("hook output, stdout: {stdout}, stderr: {stderr}");
+    let failure = match finished {
+        Ok(Ok((status, _, _))) if status.success() => return Ok(()),
+        Ok(Ok((status, _, _))) => format!("Hook failed ({status})"),
+        Ok(Err(e)) => {
+            return Err(e)
+                .wrap_err("Could not run hook")
+                .with_note(|| format!("hook: {hook}"))
+        }
+        Err(_) => {
+            kill_group(pgid);
+            format!("Hook did not finish within {}s", timeout.as_secs())
+        }
+    };
+
+    let report = if stderr.is_empty() {
+        Err(eyre::eyre!(failure))
+    } else {
+        Err(eyre::eyre!("{stderr}")).wrap_err(failure)
+    };
+    let report = report.with_note(|| format!("hook: {hook}"));
+    if stdout.is_empty() {
+        report
+    } else {
+        report.with_section(|| stdout.header("Stdout:"))
+    }
+}
+
+impl DnsProvider for Exec {
+    async fn create(
+        &self,
+        record: &TxtRecord,
+        _stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        run_hook(&self.config.present, record, HOOK_TIMEOUT)
+            .await
+            .wrap_err("The dns present hook could not create the record")
+    }
+
+    async fn cleanup(
+        &self,
+        record: &TxtRecord,
+        _stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        let Some(hook) = &self.config.cleanup else {
+            return Ok(());
+        };
+        run_hook(hook, record, HOOK_TIMEOUT)
+            .await
+            .wrap_err("The dns cleanup hook could not remove the record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> TxtRecord {
+        TxtRecord::new("example.org", "challenge-value".to_owned())
+    }
+
+    #[tokio::test]
+    async fn hook_gets_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let hook = format!(
+            "echo \"$RENEWC_DOMAIN $RENEWC_RECORD_NAME $RENEWC_TXT_VALUE\" > {}",
+            out.display()
+        );
+
+        run_hook(&hook, &record(), HOOK_TIMEOUT).await.unwrap();
+        let written = std::fs::read_to_string(out).unwrap();
+        assert_eq!(
+            written.trim(),
+            "example.org _acme-challenge.example.org challenge-value"
+        );
+    }
+
+    #[tokio::test]
+    async fn failure_is_reported() {
+        let err = run_hook("echo api said no >&2; exit 3", &record(), HOOK_TIMEOUT)
+            .await
+            .unwrap_err();
+        let report = format!("{err:?}");
+        assert!(report.contains("exit status: 3"), "{report}");
+        assert!(report.contains("api said no"), "{report}");
+    }
+
+    #[tokio::test]
+    async fn hanging_hook_times_out() {
+        let err = run_hook(
+            "echo waiting for input >&2; sleep 60",
+            &record(),
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap_err();
+        let report = format!("{err:?}");
+        assert!(report.contains("did not finish"), "{report}");
+        assert!(report.contains("waiting for input"), "{report}");
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_started_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let published = dir.path().join("published");
+        let hook = format!("(sleep 1; touch {}) & sleep 60", published.display());
+        let err = run_hook(&hook, &record(), Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("did not finish"), "{err:?}");
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(
+            !published.exists(),
+            "command started by the hook kept running"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::io::{Read, Write};
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+
+use super::{DnsProvider, TxtRecord};
+use crate::config::Config;
+
+pub struct Manual {
+    non_interactive: bool,
+}
+
+impl Manual {
+    pub(super) fn new(config: &Config) -> Self {
+        Self {
+            non_interactive: config.non_interactive,
+        }
+    }
+}
+
+impl DnsProvider for Manual {
+    async fn create(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        use std::io::IsTerminal;
+        if self.non_interactive || !std::io::stdin().is_terminal() {
+            return Err(eyre::eyre!(
+                "The manual dns provider needs someone to create the records"
+            ))
+            .suggestion("Run renewc interactively or pick another `--dns-provider`");
+        }
+
+        writeln!(
+            stdout,
+            "create this TXT record then press enter to continue:\n\t{} TXT \"{}\"",
+            record.name, record.value
+        )
+        .unwrap();
+        stdout.flush().unwrap();
+        tokio::task::spawn_blocking(|| std::io::stdin().read_exact(&mut [0]))
+            .await
+            .expect("reading stdin does not panic")
+            .wrap_err("Could not read confirmation that the record was created")
+            .suggestion("Pick a non interactive `--dns-provider` like `exec` or `rfc2136`")
+    }
+
+    async fn cleanup(
+        &self,
+        record: &TxtRecord,
+        stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        writeln!(stdout, "the TXT record for {} can be removed", record.name).unwrap();
+        Ok(())
+    }
+}
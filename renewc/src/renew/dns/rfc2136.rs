@@ -0,0 +1,425 @@
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use ring::hmac;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{DnsProvider, TxtRecord};
+use crate::config::{check_dns_name, Rfc2136Config, TsigAlgorithm};
+
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const OPCODE_UPDATE: u16 = 5;
+const RECORD_TTL: u32 = 60;
+/// Allowed clock difference between us and the server in seconds
+const FUDGE: u16 = 300;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Rfc2136 {
+    config: Rfc2136Config,
+}
+
+impl Rfc2136 {
+    pub(super) fn new(config: Rfc2136Config) -> Self {
+        Self { config }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Add,
+    Delete,
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) -> eyre::Result<()> {
+    check_dns_name(name)?;
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        let len = u8::try_from(label.len()).expect("checked labels are at most 63 bytes");
+        buf.push(len);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+fn update_message(
+    id: u16,
+    zone: &str,
+    record: &TxtRecord,
+    action: Action,
+) -> eyre::Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(512);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    // zone, prerequisite, update and additional section record counts
+    for count in [1u16, 0, 1, 0] {
+        msg.extend_from_slice(&count.to_be_bytes());
+    }
+
+    push_name(&mut msg, zone)?;
+    msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // RFC 2136 section 2.5.1 (add to an RRset) and 2.5.4 (delete an RR
+    // from an RRset), deleting only our record leaves those of parallel
+    // requests alone.
+    let (class, ttl) = match action {
+        Action::Add => (CLASS_IN, RECORD_TTL),
+        Action::Delete => (CLASS_NONE, 0),
+    };
+    push_name(&mut msg, &record.name)?;
+    msg.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    msg.extend_from_slice(&class.to_be_bytes());
+    msg.extend_from_slice(&ttl.to_be_bytes());
+
+    let value = record.value.as_bytes();
+    let value_len = u8::try_from(value.len()).expect("challenge values are 43 bytes");
+    msg.extend_from_slice(&(u16::from(value_len) + 1).to_be_bytes());
+    msg.push(value_len);
+    msg.extend_from_slice(value);
+    Ok(msg)
+}
+
+fn algorithm_name(algorithm: TsigAlgorithm) -> &'static str {
+    match algorithm {
+        TsigAlgorithm::HmacSha1 => "hmac-sha1.",
+        TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+        TsigAlgorithm::HmacSha384 => "hmac-sha384.",
+        TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+    }
+}
+
+fn ring_algorithm(algorithm: TsigAlgorithm) -> hmac::Algorithm {
+    match algorithm {
+        TsigAlgorithm::HmacSha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        TsigAlgorithm::HmacSha256 => hmac::HMAC_SHA256,
+        TsigAlgorithm::HmacSha384 => hmac::HMAC_SHA384,
+        TsigAlgorithm::HmacSha512 => hmac::HMAC_SHA512,
+    }
+}
+
+/// Everything in the TSIG record that is covered by the MAC, see
+/// RFC 8945 section 4.3.3
+fn tsig_variables(
+    key_name: &str,
+    algorithm: TsigAlgorithm,
+    time_signed: u64,
+) -> eyre::Result<Vec<u8>> {
+    let mut vars = Vec::new();
+    push_name(&mut vars, &key_name.to_lowercase())?;
+    vars.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    vars.extend_from_slice(&0u32.to_be_bytes()); // ttl
+    push_name(&mut vars, algorithm_name(algorithm))?;
+    vars.extend_from_slice(&time_signed.to_be_bytes()[2..]); // 48 bit
+    vars.extend_from_slice(&FUDGE.to_be_bytes());
+    vars.extend_from_slice(&0u16.to_be_bytes()); // error
+    vars.extend_from_slice(&0u16.to_be_bytes()); // other len
+    Ok(vars)
+}
+
+/// Append a TSIG record (RFC 8945) signing the message
+fn sign(msg: &mut Vec<u8>, config: &Rfc2136Config, time_signed: u64) -> eyre::Result<()> {
+    let key = hmac::Key::new(ring_algorithm(config.key_algorithm), &config.key_secret);
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(msg);
+    ctx.update(&tsig_variables(
+        &config.key_name,
+        config.key_algorithm,
+        time_signed,
+    )?);
+    let mac = ctx.sign();
+    let mac = mac.as_ref();
+
+    let mut rdata = Vec::new();
+    push_name(&mut rdata, algorithm_name(config.key_algorithm))?;
+    rdata.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    rdata.extend_from_slice(&FUDGE.to_be_bytes());
+    let mac_len = u16::try_from(mac.len()).expect("macs are at most 64 bytes");
+    rdata.extend_from_slice(&mac_len.to_be_bytes());
+    rdata.extend_from_slice(mac);
+    rdata.extend_from_slice(&msg[0..2]); // original id
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // error
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // other len
+
+    push_name(msg, &config.key_name.to_lowercase())?;
+    msg.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    msg.extend_from_slice(&0u32.to_be_bytes());
+    let rdata_len = u16::try_from(rdata.len()).expect("tsig record is small");
+    msg.extend_from_slice(&rdata_len.to_be_bytes());
+    msg.extend_from_slice(&rdata);
+
+    // the tsig record is the only one in the additional section
+    msg[11] = 1;
+    Ok(())
+}
+
+fn rcode_name(rcode: u8) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown error",
+    }
+}
+
+// The TSIG record of the response is not verified. A forged success can
+// at worst make the certificate authority fail to validate.
+fn check_response(id: u16, response: &[u8]) -> eyre::Result<()> {
+    if response.len() < 12 {
+        return Err(eyre::eyre!("DNS server sent a malformed response"));
+    }
+    if response[0..2] != id.to_be_bytes() {
+        return Err(eyre::eyre!("DNS server answered a different request"));
+    }
+
+    let rcode = response[3] & 0x0f;
+    let report = match rcode {
+        0 => return Ok(()),
+        code => Err(eyre::eyre!(
+            "DNS server refused the update: {}",
+            rcode_name(code)
+        )),
+    };
+    match rcode {
+        5 => report.suggestion(
+            "Check if the update policy of the zone allows the key to change TXT records",
+        ),
+        9 => report.suggestion(
+            "Check if the TSIG key name, algorithm and secret match those on the DNS \
+            server and if the clocks of both machines are correct",
+        ),
+        10 => report.suggestion("The record is not part of the zone, check `--rfc2136-zone`"),
+        _ => report,
+    }
+}
+
+fn with_default_port(server: &str) -> String {
+    if server.parse::<SocketAddr>().is_ok() {
+        server.to_owned()
+    } else if let Ok(ip) = server.parse::<IpAddr>() {
+        SocketAddr::new(ip, 53).to_string()
+    } else if server.contains(':') {
+        server.to_owned()
+    } else {
+        format!("{server}:53")
+    }
+}
+
+/// Updates are sent over TCP so we never have to deal with truncation
+async fn exchange(server: &str, msg: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+    let len = u16::try_from(msg.len()).expect("update messages are small");
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(msg).await?;
+
+    let len = stream.read_u16().await?;
+    let mut response = vec![0; len.into()];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+impl Rfc2136 {
+    #[tracing::instrument(skip(self))]
+    async fn update(&self, record: &TxtRecord, action: Action) -> eyre::Result<()> {
+        let id: u16 = rand::random();
+        let mut msg = update_message(id, &self.config.zone, record, action)
+            .wrap_err("Could not create DNS update")?;
+        let time_signed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is past 1970")
+            .as_secs();
+        sign(&mut msg, &self.config, time_signed).wrap_err("Could not sign DNS update")?;
+
+        let server = with_default_port(&self.config.server);
+        let response = tokio::time::timeout(TIMEOUT, exchange(&server, &msg))
+            .await
+            .map_err(|_| eyre::eyre!("DNS server did not respond in time"))
+            .and_then(|res| res.wrap_err("Could not send update to DNS server"))
+            .with_note(|| format!("server: {server}"))?;
+        check_response(id, &response)
+            .with_note(|| format!("server: {server}"))
+            .with_note(|| format!("zone: {}", self.config.zone))
+    }
+}
+
+impl DnsProvider for Rfc2136 {
+    async fn create(
+        &self,
+        record: &TxtRecord,
+        _stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        self.update(record, Action::Add).await
+    }
+
+    async fn cleanup(
+        &self,
+        record: &TxtRecord,
+        _stdout: &mut (impl Write + Send),
+    ) -> eyre::Result<()> {
+        self.update(record, Action::Delete).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Update {
+        name: String,
+        class: u16,
+        value: String,
+    }
+
+    fn read_name(msg: &[u8], mut pos: usize) -> (String, usize) {
+        let mut labels = Vec::new();
+        loop {
+            let len = usize::from(msg[pos]);
+            pos += 1;
+            if len == 0 {
+                return (labels.join("."), pos);
+            }
+            labels.push(String::from_utf8(msg[pos..pos + len].to_vec()).unwrap());
+            pos += len;
+        }
+    }
+
+    fn u16_at(msg: &[u8], pos: usize) -> u16 {
+        u16::from_be_bytes([msg[pos], msg[pos + 1]])
+    }
+
+    /// Parses the update and checks the signature, returns the
+    /// update and the response code to send.
+    fn handle(msg: &[u8], secret: &[u8]) -> (Update, u8) {
+        assert_eq!(u16_at(msg, 2) >> 11, OPCODE_UPDATE);
+        let (_zone, pos) = read_name(msg, 12);
+        let pos = pos + 4; // type and class
+
+        let (name, pos) = read_name(msg, pos);
+        let class = u16_at(msg, pos + 2);
+        let rdata_len = usize::from(u16_at(msg, pos + 8));
+        let rdata = &msg[pos + 10..pos + 10 + rdata_len];
+        let value = String::from_utf8(rdata[1..].to_vec()).unwrap();
+        let update = Update { name, class, value };
+
+        let tsig_start = pos + 10 + rdata_len;
+        let (_key_name, pos) = read_name(msg, tsig_start);
+        let key_name_end = pos;
+        let rdata_start = pos + 10;
+        let (_algorithm, pos) = read_name(msg, rdata_start);
+        let alg_time_fudge = &msg[rdata_start..pos + 8];
+        let mac_len = usize::from(u16_at(msg, pos + 8));
+        let mac = &msg[pos + 10..pos + 10 + mac_len];
+        let error_and_other = &msg[pos + 12 + mac_len..pos + 16 + mac_len];
+
+        let mut signed = msg[..tsig_start].to_vec();
+        signed[11] -= 1;
+        signed.extend_from_slice(&msg[tsig_start..key_name_end]);
+        signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        signed.extend_from_slice(&0u32.to_be_bytes());
+        signed.extend_from_slice(alg_time_fudge);
+        signed.extend_from_slice(error_and_other);
+
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let rcode = match hmac::verify(&key, &signed, mac) {
+            Ok(()) => 0,
+            Err(_) => 9,
+        };
+        (update, rcode)
+    }
+
+    /// In process stand in for an authoritative server
+    async fn stub_server(secret: Vec<u8>) -> (String, tokio::task::JoinHandle<Vec<Update>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let mut updates = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let len = stream.read_u16().await.unwrap();
+                let mut msg = vec![0; len.into()];
+                stream.read_exact(&mut msg).await.unwrap();
+
+                let (update, rcode) = handle(&msg, &secret);
+                updates.push(update);
+
+                let mut response = msg[0..2].to_vec();
+                response.extend_from_slice(&(0x8000 | OPCODE_UPDATE << 11).to_be_bytes());
+                response[3] |= rcode;
+                response.extend_from_slice(&[0; 8]);
+                stream.write_u16(12).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+            updates
+        });
+        (addr, server)
+    }
+
+    fn config(server: String, secret: &[u8]) -> Rfc2136Config {
+        Rfc2136Config {
+            server,
+            zone: "example.org".to_owned(),
+            key_name: "Renewc-Key".to_owned(),
+            key_algorithm: TsigAlgorithm::HmacSha256,
+            key_secret: secret.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_then_cleanup() {
+        let secret = b"not so secret".to_vec();
+        let (addr, server) = stub_server(secret.clone()).await;
+        let provider = Rfc2136::new(config(addr, &secret));
+        let record = TxtRecord::new("www.example.org", "challenge-value".to_owned());
+
+        let mut stdout = Vec::new();
+        provider.create(&record, &mut stdout).await.unwrap();
+        provider.cleanup(&record, &mut stdout).await.unwrap();
+
+        let updates = server.await.unwrap();
+        let expected = |class| Update {
+            name: "_acme-challenge.www.example.org".to_owned(),
+            class,
+            value: "challenge-value".to_owned(),
+        };
+        assert_eq!(updates, vec![expected(CLASS_IN), expected(CLASS_NONE)]);
+    }
+
+    #[tokio::test]
+    async fn wrong_key_is_reported() {
+        let (addr, _server) = stub_server(b"server secret".to_vec()).await;
+        let provider = Rfc2136::new(config(addr, b"other secret"));
+        let record = TxtRecord::new("example.org", "challenge-value".to_owned());
+
+        let err = provider.create(&record, &mut Vec::new()).await.unwrap_err();
+        assert!(format!("{err:?}").contains("NOTAUTH"), "{err:?}");
+    }
+
+    #[test]
+    fn default_port() {
+        assert_eq!(with_default_port("ns1.example.org"), "ns1.example.org:53");
+        assert_eq!(with_default_port("192.0.2.1"), "192.0.2.1:53");
+        assert_eq!(with_default_port("2001:db8::1"), "[2001:db8::1]:53");
+        assert_eq!(
+            with_default_port("[2001:db8::1]:5353"),
+            "[2001:db8::1]:5353"
+        );
+    }
+}
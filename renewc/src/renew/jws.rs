@@ -0,0 +1,184 @@
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use data_encoding::BASE64URL_NOPAD;
+use ring::rand::SystemRandom;
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde_json::json;
+
+/// A key that can sign ACME requests (RFC 8555 section 6.2). Used for the
+/// requests instant-acme can not make for us.
+pub(crate) enum SigningKey {
+    EcdsaP256(EcdsaKeyPair),
+    EcdsaP384(EcdsaKeyPair),
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl SigningKey {
+    pub(crate) fn from_pkcs8(der: &[u8]) -> eyre::Result<Self> {
+        let rng = SystemRandom::new();
+        if let Ok(key) =
+            EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, der, &rng)
+        {
+            return Ok(Self::EcdsaP256(key));
+        }
+        if let Ok(key) =
+            EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P384_SHA384_FIXED_SIGNING, der, &rng)
+        {
+            return Ok(Self::EcdsaP384(key));
+        }
+        if let Ok(key) = RsaKeyPair::from_pkcs8(der) {
+            return Ok(Self::Rsa(key));
+        }
+        if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            return Ok(Self::Ed25519(key));
+        }
+        Err(eyre::eyre!("Unsupported private key"))
+            .note("Supported are PKCS#8 encoded ECDSA (P-256, P-384), RSA and Ed25519 keys")
+    }
+
+    fn alg(&self) -> &'static str {
+        match self {
+            SigningKey::EcdsaP256(_) => "ES256",
+            SigningKey::EcdsaP384(_) => "ES384",
+            SigningKey::Rsa(_) => "RS256",
+            SigningKey::Ed25519(_) => "EdDSA",
+        }
+    }
+
+    /// public key as JSON Web Key (RFC 7517)
+    fn jwk(&self) -> serde_json::Value {
+        let b64 = |bytes: &[u8]| BASE64URL_NOPAD.encode(bytes);
+        let ec = |key: &EcdsaKeyPair, crv| {
+            // uncompressed point: 0x04 | x | y
+            let point = &key.public_key().as_ref()[1..];
+            let (x, y) = point.split_at(point.len() / 2);
+            json!({"crv": crv, "kty": "EC", "x": b64(x), "y": b64(y)})
+        };
+        match self {
+            SigningKey::EcdsaP256(key) => ec(key, "P-256"),
+            SigningKey::EcdsaP384(key) => ec(key, "P-384"),
+            SigningKey::Rsa(key) => {
+                let public = PublicKeyComponents::<Vec<u8>>::from(key.public());
+                json!({"e": b64(&public.e), "kty": "RSA", "n": b64(&public.n)})
+            }
+            SigningKey::Ed25519(key) => {
+                json!({"crv": "Ed25519", "kty": "OKP", "x": b64(key.public_key().as_ref())})
+            }
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> eyre::Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+        Ok(match self {
+            SigningKey::EcdsaP256(key) | SigningKey::EcdsaP384(key) => key
+                .sign(&rng, message)
+                .map_err(|_| eyre::eyre!("Signing failed"))?
+                .as_ref()
+                .to_vec(),
+            SigningKey::Rsa(key) => {
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(&signature::RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                    .map_err(|_| eyre::eyre!("Signing failed"))?;
+                signature
+            }
+            SigningKey::Ed25519(key) => key.sign(message).as_ref().to_vec(),
+        })
+    }
+
+    /// Flattened JWS. Identifies the account using `kid`, without it the
+    /// public key is embedded instead. A request without payload is a
+    /// POST-as-GET.
+    pub(crate) fn jws(
+        &self,
+        url: &str,
+        nonce: &str,
+        kid: Option<&str>,
+        payload: Option<&serde_json::Value>,
+    ) -> eyre::Result<Vec<u8>> {
+        let mut protected = json!({
+            "alg": self.alg(),
+            "nonce": nonce,
+            "url": url,
+        });
+        match kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = BASE64URL_NOPAD.encode(protected.to_string().as_bytes());
+        let payload = payload
+            .map(|payload| BASE64URL_NOPAD.encode(payload.to_string().as_bytes()))
+            .unwrap_or_default();
+        let signature = self
+            .sign(format!("{protected}.{payload}").as_bytes())
+            .wrap_err("Could not sign request")?;
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": BASE64URL_NOPAD.encode(&signature),
+        });
+        Ok(jws.to_string().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyType;
+    use crate::renew::generate_key;
+
+    fn decode(part: &serde_json::Value) -> Vec<u8> {
+        BASE64URL_NOPAD
+            .decode(part.as_str().unwrap().as_bytes())
+            .unwrap()
+    }
+
+    #[test]
+    fn signature_verifies_with_embedded_key() {
+        for (key_type, alg, verify) in [
+            (
+                KeyType::EcdsaP256,
+                "ES256",
+                &signature::ECDSA_P256_SHA256_FIXED as &dyn signature::VerificationAlgorithm,
+            ),
+            (
+                KeyType::EcdsaP384,
+                "ES384",
+                &signature::ECDSA_P384_SHA384_FIXED,
+            ),
+            (KeyType::Ed25519, "EdDSA", &signature::ED25519),
+        ] {
+            let key_pair = generate_key(key_type).unwrap();
+            let key = SigningKey::from_pkcs8(&key_pair.serialize_der()).unwrap();
+            let jws = key
+                .jws(
+                    "https://ca.example/revoke",
+                    "nonce",
+                    None,
+                    Some(&json!({"reason": 1})),
+                )
+                .unwrap();
+
+            let jws: serde_json::Value = serde_json::from_slice(&jws).unwrap();
+            let protected: serde_json::Value =
+                serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+            assert_eq!(protected["alg"], alg);
+            assert_eq!(protected["url"], "https://ca.example/revoke");
+
+            let jwk = &protected["jwk"];
+            let public_key = match jwk["kty"].as_str().unwrap() {
+                "EC" => [vec![4], decode(&jwk["x"]), decode(&jwk["y"])].concat(),
+                _ => decode(&jwk["x"]),
+            };
+            let message = format!(
+                "{}.{}",
+                jws["protected"].as_str().unwrap(),
+                jws["payload"].as_str().unwrap()
+            );
+            signature::UnparsedPublicKey::new(verify, public_key)
+                .verify(message.as_bytes(), &decode(&jws["signature"]))
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::Write;
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+use instant_acme as acme;
+use rustls::pki_types::CertificateDer;
+use serde_json::json;
+
+use super::jws::SigningKey;
+use super::{account, client};
+use crate::cert::format::PemItem;
+use crate::cert::{self, Signed};
+use crate::config::{AccountConfig, RevocationReason, RevokeConfig};
+
+fn acme_reason(reason: RevocationReason) -> acme::RevocationReason {
+    match reason {
+        RevocationReason::Unspecified => acme::RevocationReason::Unspecified,
+        RevocationReason::KeyCompromise => acme::RevocationReason::KeyCompromise,
+        RevocationReason::AffiliationChanged => acme::RevocationReason::AffiliationChanged,
+        RevocationReason::Superseded => acme::RevocationReason::Superseded,
+        RevocationReason::CessationOfOperation => acme::RevocationReason::CessationOfOperation,
+    }
+}
+
+async fn with_account_key(
+    config: &AccountConfig,
+    certificate: &[u8],
+    reason: RevocationReason,
+) -> eyre::Result<()> {
+    // a new account could not revoke a certificate it did not request
+    let Some((account, _, path)) = account::existing(&config.ca, config.production).await? else {
+        return Err(eyre::eyre!(
+            "No account stored for this certificate authority"
+        ))
+        .with_note(|| format!("account dir: {:?}", config.ca.account_dir))
+        .suggestion(
+            "Pass the same `--account-dir` and `--production` as used to request the certificate, or \
+            revoke using the certificate's private key with `--with-cert-key`",
+        );
+    };
+    tracing::debug!("revoking using account stored at: {}", path.display());
+    account
+        .revoke(&acme::RevocationRequest {
+            certificate: &CertificateDer::from(certificate),
+            reason: Some(acme_reason(reason)),
+        })
+        .await
+        .wrap_err("Certificate authority refused to revoke the certificate")
+        .suggestion("If the certificate was requested using another account use `--with-cert-key`")
+}
+
+// Instant-acme can only sign using the account key, so this request is
+// signed and send by us (RFC 8555 section 7.6).
+async fn with_cert_key(
+    config: &AccountConfig,
+    certificate: &[u8],
+    private_key: &[u8],
+    reason: RevocationReason,
+) -> eyre::Result<()> {
+    let key = SigningKey::from_pkcs8(private_key)
+        .wrap_err("Could not use the certificate's private key")?;
+    let client = client::new(&config.ca)?;
+
+    let url = config.ca.directory(config.production)?;
+    let directory = client::directory(&client, url).await?;
+    let (Some(new_nonce), Some(revoke_cert)) = (
+        directory["newNonce"].as_str(),
+        directory["revokeCert"].as_str(),
+    ) else {
+        return Err(eyre::eyre!(
+            "Certificate authority does not support revocation"
+        ))
+        .with_note(|| format!("directory: {url}"));
+    };
+
+    let payload = json!({
+        "certificate": data_encoding::BASE64URL_NOPAD.encode(certificate),
+        "reason": reason.code(),
+    });
+    client::post_jws(&client, new_nonce, revoke_cert, |nonce| {
+        key.jws(revoke_cert, nonce, None, Some(&payload))
+    })
+    .await?
+    .error_for_status()
+    .map(|_| ())
+}
+
+/// Revokes the certificate currently on disk
+#[tracing::instrument(skip(config, stdout))]
+pub async fn revoke(
+    config: &RevokeConfig,
+    reason: RevocationReason,
+    use_cert_key: bool,
+    stdout: &mut (impl Write + Send),
+) -> eyre::Result<()> {
+    let Some(Signed {
+        certificate,
+        private_key,
+        ..
+    }) = cert::load::from_output_config::<pem::Pem>(&config.output_config, stdout)?
+    else {
+        return Err(eyre::eyre!("No certificate found to revoke"))
+            .with_note(|| format!("path: {}", config.output_config.cert_path))
+            .suggestion("Pass the same domains and output options used to request it");
+    };
+    let certificate = certificate.der().into_bytes();
+
+    if use_cert_key {
+        let Some(private_key) = private_key else {
+            return Err(eyre::eyre!("No private key found next to the certificate"))
+                .suggestion("Revoke using the account key by leaving out `--with-cert-key`");
+        };
+        let private_key = private_key.der().into_bytes();
+        with_cert_key(&config.account, &certificate, &private_key, reason).await?;
+    } else {
+        with_account_key(&config.account, &certificate, reason).await?;
+    }
+
+    crate::info!(stdout, "revoked certificate, reason: {reason}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CaConfig;
+
+    #[tokio::test]
+    async fn without_stored_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AccountConfig {
+            ca: CaConfig {
+                account_dir: dir.path().to_owned(),
+                ..CaConfig::default()
+            },
+            production: false,
+        };
+
+        let err = with_account_key(&config, b"not a certificate", RevocationReason::Superseded)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No account stored for this certificate authority"
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
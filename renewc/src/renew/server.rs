@@ -1,23 +1,38 @@
 #![allow(clippy::missing_errors_doc)]
 
 use axum::extract::Path;
+use axum::http::header::USER_AGENT;
+use axum::http::HeaderMap;
 use axum::routing::get;
 use axum::{Extension, Router};
 
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
-use color_eyre::eyre;
+use color_eyre::eyre::{self, Context};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::{debug, error};
 
 use std::collections::HashMap;
 use std::future::{Future, IntoFuture};
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
 
 use crate::config::Config;
 use crate::diagnostics;
 
+mod activation;
+mod passthrough;
+
+/// Send by our own reachability check, those requests do not count as the
+/// challenge being served.
+pub(crate) const SELF_CHECK_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), " reachability check");
+
 #[derive(Debug, Clone)]
 pub struct Http01Challenge {
     pub url: String,
@@ -27,45 +42,364 @@ pub struct Http01Challenge {
 }
 
 type Token = String;
-type KeyAuth = String;
+
+#[derive(Debug)]
+struct Entry {
+    key_auth: String,
+    served: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: Mutex<HashMap<Token, Entry>>,
+    /// changes every time a challenge is served
+    served: watch::Sender<()>,
+}
+
+impl State {
+    fn all_served(&self, tokens: &[Token]) -> bool {
+        let registered = self.tokens.lock().expect("never poisoned");
+        tokens
+            .iter()
+            .all(|token| registered.get(token).map_or(true, |entry| entry.served))
+    }
+}
+
+async fn bind_addr(config: &Config, addr: SocketAddr) -> eyre::Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| diagnostics::cant_bind_port(config, addr, e))
+}
+
+/// Whether an IPv6 socket also accepts IPv4 differs per system, setting it
+/// explicitly means the IPv4 socket never collides with this one.
+fn bind_v6_only(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(true)?;
+    // like tokio's TcpListener::bind
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Listeners for a challenge server on `port`. Those systemd passed to us
+/// if there are any. Otherwise the `--bind` addresses or, without those,
+/// every interface using both IPv6 and IPv4.
+pub(crate) async fn bind(config: &Config, port: u16) -> eyre::Result<Vec<TcpListener>> {
+    let inherited =
+        activation::listeners(port).wrap_err("Could not use the socket(s) passed by systemd")?;
+    if !inherited.is_empty() {
+        return Ok(inherited);
+    }
+
+    if !config.bind.is_empty() {
+        let mut listeners = Vec::new();
+        for ip in &config.bind {
+            listeners.push(bind_addr(config, SocketAddr::new(*ip, port)).await?);
+        }
+        return Ok(listeners);
+    }
+
+    let v6 = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let v6 = match bind_v6_only(v6) {
+        Ok(listener) => listener,
+        Err(e) if matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::PermissionDenied) => {
+            return Err(diagnostics::cant_bind_port(config, v6, e));
+        }
+        Err(e) => {
+            debug!("IPv6 seems unavailable, only listening on IPv4: {e}");
+            return Ok(vec![bind_addr(config, v4).await?]);
+        }
+    };
+    Ok(vec![v6, bind_addr(config, v4).await?])
+}
 
 #[tracing::instrument(skip_all, fields(token))]
 async fn challenge(
-    Extension(key_auth): Extension<Arc<HashMap<Token, KeyAuth>>>,
+    Extension(state): Extension<Arc<State>>,
     Path(token): Path<String>,
+    headers: HeaderMap,
 ) -> String {
-    let Some(key_auth) = key_auth.get(&token) else {
+    let self_check = headers
+        .get(USER_AGENT)
+        .is_some_and(|agent| agent == SELF_CHECK_USER_AGENT);
+    let mut tokens = state.tokens.lock().expect("never poisoned");
+    let Some(entry) = tokens.get_mut(&token) else {
         error!("do not have a auth key for token");
         return "Error no auth key for token".to_owned();
     };
     debug!("got request for aut key");
-    key_auth.clone()
+    if !self_check {
+        entry.served = true;
+        state.served.send_replace(());
+    }
+    entry.key_auth.clone()
+}
+
+/// Serves http challenges until shut down or dropped. Challenges can be
+/// registered while it runs so one server can be used for multiple orders,
+/// for example the staging check followed by the production order.
+#[derive(Debug)]
+pub struct Server {
+    port: u16,
+    bind: Vec<IpAddr>,
+    state: Arc<State>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<std::io::Result<()>>,
 }
 
-pub async fn run(
-    config: &Config,
-    challenges: &[Http01Challenge],
-) -> eyre::Result<impl Future<Output = Result<(), std::io::Error>>> {
-    let key_auth: HashMap<_, _> = challenges
-        .iter()
-        .map(|c| (c.token.clone(), c.key_auth.clone()))
-        .collect();
-    let shared_state = Arc::new(key_auth);
-
-    let app = Router::new()
-        .route("/.well-known/acme-challenge/:token", get(challenge))
-        .layer(
+impl Server {
+    pub async fn start(config: &Config) -> eyre::Result<Self> {
+        let state = Arc::new(State {
+            tokens: Mutex::new(HashMap::new()),
+            served: watch::Sender::new(()),
+        });
+
+        let mut app = Router::new().route("/.well-known/acme-challenge/:token", get(challenge));
+        if let Some(passthrough) = passthrough::Passthrough::new(&config.other_requests)
+            .wrap_err("Could not set up handling of requests not for a challenge")?
+        {
+            app = app
+                .fallback(passthrough::handle)
+                .layer(Extension(Arc::new(passthrough)));
+        }
+        let app = app.layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
-                .layer(Extension(shared_state)),
+                .layer(Extension(state.clone())),
         );
 
-    let addr: SocketAddr = ([0, 0, 0, 0], config.port).into();
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .map_err(|e| diagnostics::cant_bind_port(config, e))?;
-    let server = axum::serve(listener, app).into_future();
+        let (shutdown, _) = watch::channel(false);
+        let mut servers = JoinSet::new();
+        for listener in bind(config, config.port).await? {
+            let mut stop = shutdown.subscribe();
+            let app = app
+                .clone()
+                .into_make_service_with_connect_info::<SocketAddr>();
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                // also shuts down if the sender is dropped
+                let _ = stop.wait_for(|stop| *stop).await;
+            });
+            servers.spawn(server.into_future());
+        }
+        let task = tokio::spawn(async move {
+            while let Some(res) = servers.join_next().await {
+                res.map_err(std::io::Error::other)??;
+            }
+            Ok(())
+        });
+
+        Ok(Self {
+            port: config.port,
+            bind: config.bind.clone(),
+            state,
+            shutdown,
+            task,
+        })
+    }
+
+    /// Whether this server listens where `config` asks for
+    #[must_use]
+    pub fn listens_as(&self, config: &Config) -> bool {
+        self.port == config.port && self.bind == config.bind
+    }
+
+    pub fn register(&self, challenges: &[Http01Challenge]) {
+        let mut tokens = self.state.tokens.lock().expect("never poisoned");
+        for c in challenges {
+            tokens.insert(
+                c.token.clone(),
+                Entry {
+                    key_auth: c.key_auth.clone(),
+                    served: false,
+                },
+            );
+        }
+    }
+
+    pub fn deregister(&self, challenges: &[Http01Challenge]) {
+        let mut tokens = self.state.tokens.lock().expect("never poisoned");
+        for c in challenges {
+            tokens.remove(&c.token);
+        }
+    }
+
+    /// Challenges that have not been requested yet, apart from by our own
+    /// reachability check
+    #[must_use]
+    pub fn unserved<'a>(&self, challenges: &'a [Http01Challenge]) -> Vec<&'a Http01Challenge> {
+        let tokens = self.state.tokens.lock().expect("never poisoned");
+        challenges
+            .iter()
+            .filter(|c| tokens.get(&c.token).is_some_and(|entry| !entry.served))
+            .collect()
+    }
+
+    /// Resolves once each of the challenges has been served at least once
+    pub fn all_served(&self, challenges: &[Http01Challenge]) -> impl Future<Output = ()> {
+        let state = self.state.clone();
+        let mut served = state.served.subscribe();
+        let tokens: Vec<_> = challenges.iter().map(|c| c.token.clone()).collect();
+        async move {
+            while !state.all_served(&tokens) {
+                if served.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Resolves if the server stops by itself, which only happens on error
+    pub async fn stopped(&mut self) -> eyre::Report {
+        match (&mut self.task).await {
+            Ok(Ok(())) => eyre::eyre!("Challenge server stopped unexpectedly"),
+            Ok(Err(e)) => eyre::Report::new(e).wrap_err("Challenge server ran into problem"),
+            Err(e) => eyre::Report::new(e).wrap_err("Challenge server crashed"),
+        }
+    }
+
+    /// Stops accepting connections and waits for open ones to finish
+    pub async fn shutdown(self) -> eyre::Result<()> {
+        self.shutdown.send_replace(true);
+        self.task
+            .await
+            .wrap_err("Challenge server crashed")?
+            .wrap_err("Challenge server ran into problem")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn challenge(token: &str) -> Http01Challenge {
+        Http01Challenge {
+            url: String::new(),
+            token: token.to_owned(),
+            key_auth: format!("{token}.key"),
+            id: "example.org".to_owned(),
+        }
+    }
+
+    async fn request(port: u16, token: &str, user_agent: &str) -> String {
+        reqwest::Client::new()
+            .get(format!(
+                "http://127.0.0.1:{port}/.well-known/acme-challenge/{token}"
+            ))
+            .header(reqwest::header::USER_AGENT, user_agent)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn port_in_use_on_ipv4_is_an_error() {
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::test(port, dir.path());
+
+        assert!(bind(&config, port).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn register_serve_and_shutdown() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::test(port, dir.path());
+        let server = Server::start(&config).await.unwrap();
+
+        let first = [challenge("first")];
+        server.register(&first);
+        assert_eq!(
+            request(port, "first", SELF_CHECK_USER_AGENT).await,
+            "first.key"
+        );
+        assert_eq!(server.unserved(&first).len(), 1);
+
+        // tokens can be added while running
+        let second = [challenge("second")];
+        server.register(&second);
+        let served = server.all_served(&second);
+        assert_eq!(request(port, "second", "ca").await, "second.key");
+        tokio::time::timeout(Duration::from_secs(1), served)
+            .await
+            .unwrap();
+        assert!(server.unserved(&second).is_empty());
+
+        server.deregister(&first);
+        assert_eq!(
+            request(port, "first", "ca").await,
+            "Error no auth key for token"
+        );
+
+        server.shutdown().await.unwrap();
+        // port is free again
+        std::net::TcpListener::bind(("0.0.0.0", port)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn explicit_bind_address() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(port, dir.path());
+        config.bind = vec![IpAddr::V4(Ipv4Addr::LOCALHOST)];
+
+        let listeners = bind(&config, port).await.unwrap();
+        let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        assert_eq!(addrs, [SocketAddr::from((Ipv4Addr::LOCALHOST, port))]);
+
+        let err = bind(&config, port).await.unwrap_err();
+        assert!(
+            format!("{err:?}").contains(&format!("Could not listen on 127.0.0.1:{port}")),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn proxies_other_requests() {
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let site =
+            Router::new().fallback(|uri: axum::http::Uri| async move { format!("site {uri}") });
+        tokio::spawn(axum::serve(upstream, site).into_future());
+
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(port, dir.path());
+        config.bind = vec![IpAddr::V4(Ipv4Addr::LOCALHOST)];
+        config.other_requests = crate::config::OtherRequests::Proxy(
+            format!("http://127.0.0.1:{upstream_port}").parse().unwrap(),
+        );
+        let server = Server::start(&config).await.unwrap();
+        server.register(&[challenge("token")]);
+
+        assert_eq!(request(port, "token", "ca").await, "token.key");
+        let page = reqwest::get(format!("http://127.0.0.1:{port}/blog?page=2"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(page, "site /blog?page=2");
 
-    // this needs to shutdown when done not keep going cause then next call to run will have port in use, maybe even graceful shutdown? see: https://github.com/tokio-rs/axum/blob/main/examples/graceful-shutdown/src/main.rs
-    Ok(server)
+        server.shutdown().await.unwrap();
+    }
 }
@@ -0,0 +1,90 @@
+//! Listening sockets handed to us by systemd, see `sd_listen_fds(3)`. With
+//! those renewc does not need root to listen on ports below 1025.
+
+use std::ops::Range;
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::OnceLock;
+
+use tokio::net::TcpListener;
+use tracing::{debug, warn};
+
+/// The first passed file descriptor, the ones before it are stdin/out/err
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+static INHERITED: OnceLock<Vec<std::net::TcpListener>> = OnceLock::new();
+
+fn fd_range(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<Range<RawFd>> {
+    // the variables are inherited by children, they are only meant for us
+    // if they carry our pid
+    let listen_pid: u32 = listen_pid?.parse().ok()?;
+    if listen_pid != pid {
+        return None;
+    }
+    let count: RawFd = listen_fds?.parse().ok()?;
+    Some(SD_LISTEN_FDS_START..SD_LISTEN_FDS_START.checked_add(count)?)
+}
+
+fn take_from_env() -> Vec<std::net::TcpListener> {
+    let Some(fds) = fd_range(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    ) else {
+        return Vec::new();
+    };
+
+    let mut listeners = Vec::new();
+    for fd in fds {
+        // Safety: systemd passed these to us and nothing else took
+        // ownership, this runs only once because of the OnceLock
+        let passed = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        if let Err(e) = passed.local_addr() {
+            warn!("ignoring file descriptor {fd} passed by systemd, it is not a tcp socket: {e}");
+            std::mem::forget(passed);
+            continue;
+        }
+        // the duplicate is not inherited by programs we run (hooks,
+        // systemctl) unlike the original
+        match passed.try_clone() {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => warn!("could not take over socket passed by systemd: {e}"),
+        }
+    }
+    debug!(
+        "inherited {} listening socket(s) from systemd",
+        listeners.len()
+    );
+    listeners
+}
+
+fn all() -> &'static [std::net::TcpListener] {
+    INHERITED.get_or_init(take_from_env)
+}
+
+/// Listeners passed by systemd for `port`, empty if there are none
+pub(crate) fn listeners(port: u16) -> std::io::Result<Vec<TcpListener>> {
+    let mut res = Vec::new();
+    for inherited in all() {
+        if inherited.local_addr()?.port() != port {
+            continue;
+        }
+        let listener = inherited.try_clone()?;
+        listener.set_nonblocking(true)?;
+        res.push(TcpListener::from_std(listener)?);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_for_our_pid() {
+        assert_eq!(fd_range(Some("42"), Some("2"), 42), Some(3..5));
+        assert_eq!(fd_range(Some("41"), Some("2"), 42), None);
+        assert_eq!(fd_range(None, Some("2"), 42), None);
+        assert_eq!(fd_range(Some("42"), None, 42), None);
+        assert_eq!(fd_range(Some("42"), Some("many"), 42), None);
+    }
+}
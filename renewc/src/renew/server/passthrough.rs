@@ -0,0 +1,176 @@
+//! Handles requests to the challenge server that are not for a challenge.
+//! Renewing on a live host then only briefly swaps who owns the port.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{self, HeaderMap, HeaderName, HOST};
+use axum::http::{StatusCode, Uri, Version};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Extension;
+use color_eyre::eyre::{self, Context};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use tracing::{debug, warn};
+
+use crate::config::OtherRequests;
+
+type Client = hyper_util::client::legacy::Client<HttpsConnector<HttpConnector>, Body>;
+
+/// Only meant for a single connection, never forwarded (RFC 9110 section 7.6.1)
+const HOP_BY_HOP: [HeaderName; 6] = [
+    header::CONNECTION,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Debug)]
+pub(super) enum Passthrough {
+    Proxy { client: Box<Client>, upstream: Uri },
+    RedirectHttps,
+}
+
+impl Passthrough {
+    pub(super) fn new(other: &OtherRequests) -> eyre::Result<Option<Self>> {
+        Ok(match other {
+            OtherRequests::NotFound => None,
+            OtherRequests::RedirectHttps => Some(Self::RedirectHttps),
+            OtherRequests::Proxy(upstream) => {
+                let connector = HttpsConnectorBuilder::new()
+                    .try_with_platform_verifier()
+                    .wrap_err("Could not load the system's root certificates")?
+                    .https_or_http()
+                    .enable_http1()
+                    .build();
+                Some(Self::Proxy {
+                    client: Box::new(
+                        hyper_util::client::legacy::Client::builder(TokioExecutor::new())
+                            .build(connector),
+                    ),
+                    upstream: upstream.clone(),
+                })
+            }
+        })
+    }
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+}
+
+fn upstream_uri(upstream: &Uri, uri: &Uri) -> Result<Uri, axum::http::Error> {
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let mut parts = upstream.clone().into_parts();
+    parts.path_and_query = Some(path.parse()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+async fn proxy(client: &Client, upstream: &Uri, peer: SocketAddr, req: Request) -> Response {
+    let (mut parts, body) = req.into_parts();
+    parts.uri = match upstream_uri(upstream, &parts.uri) {
+        Ok(uri) => uri,
+        Err(e) => {
+            debug!("could not forward request for {}: {e}", parts.uri);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    // the client only speaks http1 to the upstream
+    parts.version = Version::HTTP_11;
+    remove_hop_by_hop(&mut parts.headers);
+    // the host header is kept so name based virtual hosts keep working
+    parts.headers.append(
+        "x-forwarded-for",
+        peer.ip()
+            .to_string()
+            .parse()
+            .expect("ip addresses are valid header values"),
+    );
+    parts
+        .headers
+        .insert("x-forwarded-proto", "http".parse().expect("valid header"));
+
+    match client.request(Request::from_parts(parts, body)).await {
+        Ok(response) => {
+            let mut response = response.map(Body::new);
+            remove_hop_by_hop(response.headers_mut());
+            response
+        }
+        Err(e) => {
+            warn!("could not reach upstream {upstream}: {e}");
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+fn redirect_https(req: &Request) -> Response {
+    let Some(host) = req.headers().get(HOST).and_then(|h| h.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing host header").into_response();
+    };
+    // drop the port, ipv6 addresses are enclosed in brackets
+    let host = match host.find(']') {
+        Some(end) => &host[..=end],
+        None => host.split_once(':').map_or(host, |(host, _)| host),
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    // temporary as renewc only briefly owns the port
+    Redirect::temporary(&format!("https://{host}{path}")).into_response()
+}
+
+pub(super) async fn handle(
+    Extension(passthrough): Extension<Arc<Passthrough>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> Response {
+    match passthrough.as_ref() {
+        Passthrough::Proxy { client, upstream } => proxy(client, upstream, peer, req).await,
+        Passthrough::RedirectHttps => redirect_https(&req),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_path_and_query() {
+        let upstream: Uri = "http://127.0.0.1:8080".parse().unwrap();
+        let uri: Uri = "/blog/post?page=2".parse().unwrap();
+        assert_eq!(
+            upstream_uri(&upstream, &uri).unwrap(),
+            "http://127.0.0.1:8080/blog/post?page=2"
+        );
+    }
+
+    #[test]
+    fn redirect_drops_port() {
+        for (host, expected) in [
+            ("example.org", "https://example.org/a?b"),
+            ("example.org:80", "https://example.org/a?b"),
+            ("[2001:db8::1]:80", "https://[2001:db8::1]/a?b"),
+            ("[2001:db8::1]", "https://[2001:db8::1]/a?b"),
+        ] {
+            let req = Request::builder()
+                .uri("/a?b")
+                .header(HOST, host)
+                .body(Body::empty())
+                .unwrap();
+            let response = redirect_https(&req);
+            assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+            assert_eq!(
+                response.headers()[header::LOCATION],
+                expected,
+                "host: {host}"
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use color_eyre::eyre::{self, Context};
+use rcgen::{Certificate, CertificateParams, CustomExtension};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::config::Config;
+
+/// RFC 8737 section 6.2
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+#[derive(Debug, Clone)]
+pub struct TlsAlpn01Challenge {
+    pub url: String,
+    /// SHA-256 digest of the key authorization
+    pub digest: Vec<u8>,
+    pub id: String,
+}
+
+/// Self signed certificate for the identifier carrying the key
+/// authorization digest in the critical acmeIdentifier extension
+fn validation_cert(challenge: &TlsAlpn01Challenge) -> eyre::Result<CertifiedKey> {
+    let mut params = CertificateParams::new(vec![challenge.id.clone()]);
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&challenge.digest)];
+    let cert = Certificate::from_params(params)?;
+
+    let der = cert.serialize_der()?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()));
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .wrap_err("Could not use key of validation certificate")?;
+    Ok(CertifiedKey::new(vec![der.into()], key))
+}
+
+/// The server name the certificate authority asks for during validation.
+/// For an ip address that is its reverse dns name (RFC 8738 section 6).
+fn server_name(id: &str) -> String {
+    match id.parse() {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let nibbles: String = ip
+                .octets()
+                .iter()
+                .rev()
+                .map(|byte| format!("{:x}.{:x}.", byte & 0xf, byte >> 4))
+                .collect();
+            format!("{nibbles}ip6.arpa")
+        }
+        Err(_) => id.to_owned(),
+    }
+}
+
+#[derive(Debug)]
+struct Resolver {
+    certs: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        // only answer validation requests
+        if !hello.alpn()?.any(|protocol| protocol == ACME_TLS_ALPN) {
+            return None;
+        }
+        self.certs.get(hello.server_name()?).cloned()
+    }
+}
+
+pub async fn run(
+    config: &Config,
+    challenges: &[TlsAlpn01Challenge],
+) -> eyre::Result<impl Future<Output = Result<(), std::io::Error>>> {
+    let certs = challenges
+        .iter()
+        .map(|c| Ok((server_name(&c.id), Arc::new(validation_cert(c)?))))
+        .collect::<eyre::Result<_>>()?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(Resolver { certs }));
+    tls.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(tls));
+
+    let listeners = super::server::bind(config, config.tls_port).await?;
+    Ok(async move {
+        // dropping the set stops accepting
+        let mut accepting = JoinSet::new();
+        for listener in listeners {
+            accepting.spawn(accept(listener, acceptor.clone()));
+        }
+        match accepting.join_next().await {
+            Some(res) => res.map_err(std::io::Error::other)?,
+            None => Ok(()),
+        }
+    })
+}
+
+async fn accept(listener: TcpListener, acceptor: TlsAcceptor) -> Result<(), std::io::Error> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            // the handshake is all the validation needs
+            match acceptor.accept(stream).await {
+                Ok(mut stream) => {
+                    debug!("served validation certificate to: {peer}");
+                    let _ = stream.shutdown().await;
+                }
+                Err(e) => debug!("tls handshake with {peer} failed: {e}"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    #[test]
+    fn validation_cert_has_acme_identifier() {
+        let challenge = TlsAlpn01Challenge {
+            url: String::new(),
+            digest: vec![7; 32],
+            id: "example.org".to_owned(),
+        };
+        let certified = validation_cert(&challenge).unwrap();
+        let (_, cert) = X509Certificate::from_der(&certified.cert[0]).unwrap();
+
+        let acme_id = cert
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == "1.3.6.1.5.5.7.1.31")
+            .expect("acmeIdentifier extension must be present");
+        assert!(acme_id.critical);
+        // DER octet string: tag, length then the digest
+        assert_eq!(acme_id.value[..2], [0x04, 32]);
+        assert_eq!(acme_id.value[2..], [7; 32]);
+    }
+
+    #[test]
+    fn reverse_dns_server_name() {
+        assert_eq!(server_name("example.org"), "example.org");
+        assert_eq!(server_name("192.0.2.1"), "1.2.0.192.in-addr.arpa");
+        assert_eq!(
+            server_name("2001:db8::1"),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
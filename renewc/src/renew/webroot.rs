@@ -0,0 +1,107 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{self, Context};
+use color_eyre::Help;
+
+use super::server::Http01Challenge;
+
+fn token_path(webroot: &Path, token: &str) -> eyre::Result<PathBuf> {
+    // tokens are base64url, anything else could escape the webroot
+    if token.is_empty()
+        || !token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(eyre::eyre!("Certificate authority sent an invalid token"))
+            .with_note(|| format!("token: {token:?}"));
+    }
+    Ok(webroot
+        .join(".well-known")
+        .join("acme-challenge")
+        .join(token))
+}
+
+/// Write the key authorization for each challenge to
+/// `<webroot>/.well-known/acme-challenge/<token>` so the web server
+/// already running on port 80 can serve it.
+pub(super) fn write(webroot: &Path, challenges: &[Http01Challenge]) -> eyre::Result<()> {
+    let res = challenges.iter().try_for_each(|challenge| {
+        let path = token_path(webroot, &challenge.token)?;
+        let dir = path.parent().expect("token path always has parents");
+        fs::create_dir_all(dir)
+            .wrap_err("Could not create challenge directory in webroot")
+            .with_note(|| format!("path: {dir:?}"))?;
+        fs::write(&path, &challenge.key_auth)
+            .wrap_err("Could not write challenge file to webroot")
+            .with_note(|| format!("path: {path:?}"))
+    });
+
+    if res.is_err() {
+        remove_files(webroot, challenges);
+    }
+    res
+}
+
+fn remove_files(webroot: &Path, challenges: &[Http01Challenge]) -> Vec<eyre::Report> {
+    challenges
+        .iter()
+        .filter_map(|challenge| {
+            let path = token_path(webroot, &challenge.token).ok()?;
+            match fs::remove_file(&path) {
+                Ok(()) => None,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => Some(
+                    eyre::Report::new(e)
+                        .wrap_err(format!("Could not remove challenge file: {path:?}")),
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Remove the challenge files again. Failing to do so is not fatal for
+/// the renewal, the user is warned instead.
+pub(super) fn remove(webroot: &Path, challenges: &[Http01Challenge], stdout: &mut impl Write) {
+    for e in remove_files(webroot, challenges) {
+        crate::warn!(stdout, "{e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(token: &str) -> Http01Challenge {
+        Http01Challenge {
+            url: String::new(),
+            token: token.to_owned(),
+            key_auth: format!("{token}.thumbprint"),
+            id: "example.org".to_owned(),
+        }
+    }
+
+    #[test]
+    fn write_then_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let challenges = [challenge("tok-en_1"), challenge("token2")];
+        write(dir.path(), &challenges).unwrap();
+
+        let path = dir.path().join(".well-known/acme-challenge/tok-en_1");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "tok-en_1.thumbprint");
+
+        let mut stdout = Vec::new();
+        remove(dir.path(), &challenges, &mut stdout);
+        assert!(!path.exists());
+        assert!(stdout.is_empty());
+    }
+
+    #[test]
+    fn token_can_not_escape_webroot() {
+        let dir = tempfile::tempdir().unwrap();
+        let challenges = [challenge("ok"), challenge("../../evil")];
+        assert!(write(dir.path(), &challenges).is_err());
+        assert!(!dir.path().join(".well-known/acme-challenge/ok").exists());
+    }
+}
@@ -3,11 +3,20 @@ use color_eyre::eyre;
 use std::process::Command;
 
 pub fn systemctl(args: &[&'static str], service: &str) -> Result<()> {
-    let output = Command::new("systemctl")
-        .args(args)
-        .arg(service)
-        .output()
-        .wrap_err("Could not run systemctl")?;
+    let mut command = Command::new("systemctl");
+    command.args(args).arg(service);
+    run(command)
+}
+
+/// Makes systemd pick up changed unit files
+pub fn daemon_reload() -> Result<()> {
+    let mut command = Command::new("systemctl");
+    command.arg("daemon-reload");
+    run(command)
+}
+
+fn run(mut command: Command) -> Result<()> {
+    let output = command.output().wrap_err("Could not run systemctl")?;
 
     if output.status.success() {
         return Ok(());
@@ -24,7 +24,7 @@ async fn haproxy_binds_port() {
     let mut config = Config::test(bound_port, &dir.path().join("test_cert"));
     config.diagnostics.haproxy.path = path;
 
-    let err = run::<Pem>(&mut InstantAcme {}, &mut TestPrinter, &config, true)
+    let err = run::<Pem>(&mut InstantAcme::default(), &mut TestPrinter, &config, true)
         .await
         .unwrap_err();
     let test = format!("{err:?}");
@@ -45,7 +45,7 @@ async fn insufficent_permissions() {
     let dir = tempdir().unwrap();
     let config = Config::test(42, &dir.path().join("test_cert"));
 
-    let err = run::<Pem>(&mut InstantAcme {}, &mut TestPrinter, &config, true)
+    let err = run::<Pem>(&mut InstantAcme::default(), &mut TestPrinter, &config, true)
         .await
         .unwrap_err();
     let test = format!("{err:?}");
@@ -63,7 +63,7 @@ async fn port_forward_suggestion_includes_ip() {
     // port 1119 is assigned to a use by the IANA
     // and should not route to the current machine
     let config = Config::test(1119, &dir.path());
-    let err = run::<Pem>(&mut InstantAcme {}, &mut TestPrinter, &config, true)
+    let err = run::<Pem>(&mut InstantAcme::default(), &mut TestPrinter, &config, true)
         .await
         .unwrap_err();
 
@@ -2,7 +2,7 @@ use pem::Pem;
 use renewc::cert::{load, store, Signed};
 use renewc::Config;
 
-use renewc::config::Output;
+use renewc::config::{Output, RevokedFiles};
 use renewc_test_support::gen_cert;
 use renewc_test_support::TestPrinter;
 use time::OffsetDateTime;
@@ -41,3 +41,101 @@ async fn der_and_pem_equal() {
         );
     }
 }
+
+#[tokio::test]
+async fn stored_without_key() {
+    renewc_test_support::setup_color_eyre();
+    renewc_test_support::setup_tracing();
+
+    let valid_till = OffsetDateTime::now_utc();
+    let mut original: Signed<Pem> = gen_cert::generate_cert_with_chain(
+        valid_till,
+        false,
+        &[String::from("testdomain.org")],
+    );
+    // certificates requested using a CSR have no key
+    original.private_key = None;
+
+    for format in [
+        Output::PemSingleFile,
+        Output::PemSeperateKey,
+        Output::PemSeperateChain,
+        Output::PemAllSeperate,
+        Output::Der,
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(42, dir.path());
+        config.output_config.output = dbg!(format);
+        store::on_disk(&config, original.clone(), &mut TestPrinter).unwrap();
+        assert!(!config.output_config.key_path.as_path().exists());
+
+        let loaded = load::from_disk(&config, &mut TestPrinter).unwrap().unwrap();
+        assert_eq!(
+            loaded, original,
+            "certs without key stored then loaded from {format:?} are different then originally stored"
+        );
+    }
+}
+
+#[tokio::test]
+async fn stored_without_key_removes_previous_key() {
+    renewc_test_support::setup_color_eyre();
+    renewc_test_support::setup_tracing();
+
+    let with_key: Signed<Pem> = gen_cert::generate_cert_with_chain(
+        OffsetDateTime::now_utc(),
+        false,
+        &[String::from("testdomain.org")],
+    );
+    let mut without_key = with_key.clone();
+    without_key.private_key = None;
+
+    for format in [Output::PemSeperateKey, Output::PemAllSeperate, Output::Der] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::test(42, dir.path());
+        config.output_config.output = dbg!(format);
+        store::on_disk(&config, with_key.clone(), &mut TestPrinter).unwrap();
+        assert!(config.output_config.key_path.as_path().exists());
+
+        store::on_disk(&config, without_key.clone(), &mut TestPrinter).unwrap();
+        assert!(!config.output_config.key_path.as_path().exists());
+        let loaded: Signed<Pem> = load::from_disk(&config, &mut TestPrinter).unwrap().unwrap();
+        assert!(loaded.private_key.is_none(), "{format:?} kept the old key");
+    }
+}
+
+#[tokio::test]
+async fn revoked_files_renamed() {
+    renewc_test_support::setup_color_eyre();
+    renewc_test_support::setup_tracing();
+
+    let dir = tempfile::tempdir().unwrap();
+    let original: Signed<Pem> = gen_cert::generate_cert_with_chain(
+        OffsetDateTime::now_utc(),
+        false,
+        &[String::from("testdomain.org")],
+    );
+
+    let mut config = Config::test(42, dir.path());
+    config.output_config.output = Output::Der;
+    store::on_disk(&config, original, &mut TestPrinter).unwrap();
+    let stored = std::fs::read_dir(dir.path()).unwrap().count();
+
+    store::revoked(
+        &config.output_config,
+        RevokedFiles::Rename,
+        &mut TestPrinter,
+    )
+    .unwrap();
+    assert!(
+        load::from_output_config::<Pem>(&config.output_config, &mut TestPrinter)
+            .unwrap()
+            .is_none()
+    );
+    let renamed = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".revoked"))
+        .count();
+    assert_eq!(renamed, stored);
+}